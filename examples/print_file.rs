@@ -0,0 +1,68 @@
+// prints an image with the driver alone, without the bot:
+//
+// cargo run --example print_file -- foo.png [/dev/usb/lp0]
+//
+// DITHER and GAMMA are read as the bot reads them
+use printer_bot_rs::{dither, driver, raster};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().expect("usage: print_file <image> [device]");
+    let device = args.next().unwrap_or_else(|| "/dev/usb/lp0".to_string());
+
+    let algorithm = std::env::var("DITHER").map_or(dither::Dither::FloydSteinberg, |x| {
+        dither::parse(&x).unwrap_or_else(|| panic!("invalid DITHER: {x}"))
+    });
+    let gamma = std::env::var("GAMMA")
+        .map(|x| {
+            x.parse()
+                .ok()
+                .filter(|&x: &f32| x > 0.0)
+                .expect("invalid GAMMA")
+        })
+        .unwrap_or(raster::DEFAULT_GAMMA);
+
+    let mut printer = driver::PrinterCommander::main(&device)?;
+
+    printer.reset()?;
+    printer.initilize()?;
+    printer.get_status()?;
+
+    let status = printer.read_status()?;
+    status.is_ready()?;
+
+    // scale the image to the printable width of the loaded media
+    let line_bytes = status.model().line_bytes();
+    let (first_dot, last_dot) = status
+        .printable_range()
+        .unwrap_or((0, line_bytes as u16 * 8 - 1));
+    let width = (last_dot - first_dot + 1) as u32;
+
+    let mut img = raster::resize(&image::open(&path)?.to_luma8(), width, 1);
+    raster::gamma_correct(&mut img, gamma);
+
+    let pixels = dither::dither(&img, algorithm);
+
+    let raster = raster::Raster {
+        rows: raster::trim_blank_rows(&pixels, width, img.height()),
+        pixels,
+        width,
+        line_bytes,
+        first_dot,
+        last_dot,
+        mirror: false,
+        high_resolution: false,
+        min_lines: 0,
+    };
+
+    printer.print_lines(
+        &status,
+        raster.line_count(),
+        raster.lines(),
+        &Default::default(),
+    )?;
+
+    println!("printed {} lines of {}", raster.line_count(), path);
+
+    Ok(())
+}
@@ -0,0 +1,355 @@
+use image::GrayImage;
+
+// the output of every algorithm is one palette index per pixel, 0 is black and 1 is white
+#[derive(Debug, Clone, Copy)]
+pub enum Dither {
+    FloydSteinberg,
+    // alternates the direction of every row, so the error doesn't always drift the same way
+    FloydSteinbergSerpentine,
+    // passes on only 3/4 of the error, so light areas go white and edges stay clean, at the
+    // cost of losing detail in the shadows. good for text over pictures
+    Atkinson,
+    // fully deterministic, the same input always gives the same raster on any machine
+    Ordered,
+    // plain cutoff, pixels brighter than the level are white. best for text and line art
+    Threshold(u8),
+    // a threshold at the level that best separates the dark and the light pixels of this
+    // picture, so scans and photos of documents work whatever their exposure
+    Otsu,
+    // thresholds what is darker than `low` or brighter than `high` and diffuses only the
+    // midtones, so text stays crisp and photos stay smooth in the same picture
+    Hybrid { low: u8, high: u8 },
+}
+
+// the values of DITHER: `floyd_steinberg`, `ordered`, `threshold:100`, `hybrid:48-208`, ...
+pub fn parse(value: &str) -> Option<Dither> {
+    match value {
+        "floyd_steinberg" => Some(Dither::FloydSteinberg),
+        "floyd_steinberg_serpentine" => Some(Dither::FloydSteinbergSerpentine),
+        "ordered" => Some(Dither::Ordered),
+        "atkinson" => Some(Dither::Atkinson),
+        "none" => Some(Dither::Threshold(127)),
+        "threshold:otsu" => Some(Dither::Otsu),
+        "hybrid" => Some(Dither::Hybrid { low: 48, high: 208 }),
+        _ => {
+            if let Some(level) = value.strip_prefix("threshold:") {
+                return level.parse().ok().map(Dither::Threshold);
+            }
+
+            // the midtone band, low has to be below high
+            let (low, high) = value.strip_prefix("hybrid:")?.split_once('-')?;
+            let (low, high) = (low.parse().ok()?, high.parse().ok()?);
+
+            (low < high).then_some(Dither::Hybrid { low, high })
+        }
+    }
+}
+
+pub fn dither(img: &GrayImage, algorithm: Dither) -> Vec<u8> {
+    match algorithm {
+        Dither::FloydSteinberg => floyd_steinberg(img),
+        Dither::FloydSteinbergSerpentine => floyd_steinberg_serpentine(img),
+        Dither::Atkinson => atkinson(img),
+        Dither::Ordered => ordered(img),
+        Dither::Threshold(level) => threshold(img, level),
+        Dither::Otsu => threshold(img, otsu_level(img)),
+        Dither::Hybrid { low, high } => hybrid(img, low, high),
+    }
+}
+
+pub fn threshold(img: &GrayImage, level: u8) -> Vec<u8> {
+    img.pixels().map(|x| (x.0[0] > level) as u8).collect()
+}
+
+// otsu's method: the level with the largest variance between the pixels below and above it
+fn otsu_level(img: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    img.pixels().for_each(|x| histogram[x.0[0] as usize] += 1);
+
+    let total = img.pixels().len() as f64;
+    let sum = histogram
+        .iter()
+        .enumerate()
+        .map(|(level, &count)| level as f64 * count as f64)
+        .sum::<f64>();
+
+    let (mut below, mut below_sum) = (0.0, 0.0);
+    let (mut best_level, mut best_variance) = (0, 0.0);
+
+    for (level, &count) in histogram.iter().enumerate() {
+        below += count as f64;
+        below_sum += level as f64 * count as f64;
+
+        let above = total - below;
+
+        if below == 0.0 || above == 0.0 {
+            continue;
+        }
+
+        let mean_below = below_sum / below;
+        let mean_above = (sum - below_sum) / above;
+        let variance = below * above * (mean_below - mean_above).powi(2);
+
+        if variance > best_variance {
+            best_level = level;
+            best_variance = variance;
+        }
+    }
+
+    best_level as u8
+}
+
+fn floyd_steinberg(img: &GrayImage) -> Vec<u8> {
+    use exoquant::*;
+
+    let palette = vec![Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+
+    let ditherer = ditherer::FloydSteinberg::vanilla();
+    let colorspace = SimpleColorSpace::default();
+    let remapper = Remapper::new(&palette, &colorspace, &ditherer);
+
+    let image = img
+        .pixels()
+        .map(|x| Color::new(x.0[0], x.0[0], x.0[0], 255))
+        .collect::<Vec<Color>>();
+
+    remapper.remap(&image, img.width() as usize)
+}
+
+fn floyd_steinberg_serpentine(img: &GrayImage) -> Vec<u8> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+
+    let mut values = img.pixels().map(|x| x.0[0] as f32).collect::<Vec<_>>();
+    let mut output = vec![0u8; width * height];
+
+    for y in 0..height {
+        let reversed = y % 2 == 1;
+
+        for i in 0..width {
+            let x = if reversed { width - 1 - i } else { i };
+
+            // neighbours in the direction this row is scanned, and against it
+            let next = Some(x + 1).filter(|&x| x < width);
+            let previous = x.checked_sub(1);
+            let (ahead, behind) = if reversed {
+                (previous, next)
+            } else {
+                (next, previous)
+            };
+
+            let old = values[y * width + x];
+            let white = old >= 128.0;
+            output[y * width + x] = white as u8;
+
+            let error = old - if white { 255.0 } else { 0.0 };
+
+            let mut spread = |x: Option<usize>, y: usize, weight: f32| {
+                if let Some(x) = x {
+                    if y < height {
+                        values[y * width + x] += error * weight;
+                    }
+                }
+            };
+
+            spread(ahead, y, 7.0 / 16.0);
+            spread(behind, y + 1, 3.0 / 16.0);
+            spread(Some(x), y + 1, 5.0 / 16.0);
+            spread(ahead, y + 1, 1.0 / 16.0);
+        }
+    }
+
+    output
+}
+
+fn atkinson(img: &GrayImage) -> Vec<u8> {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+
+    let mut values = img.pixels().map(|x| x.0[0] as f32).collect::<Vec<_>>();
+    let mut output = vec![0u8; values.len()];
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+
+            let old = values[i];
+            let white = old >= 128.0;
+            output[i] = white as u8;
+
+            let error = (old - if white { 255.0 } else { 0.0 }) / 8.0;
+
+            for (dx, dy) in [(1, 0), (2, 0), (-1, 1), (0, 1), (1, 1), (0, 2)] {
+                let (nx, ny) = (x + dx, y + dy);
+
+                if nx >= 0 && nx < width && ny < height {
+                    values[(ny * width + nx) as usize] += error;
+                }
+            }
+        }
+    }
+
+    output
+}
+
+fn hybrid(img: &GrayImage, low: u8, high: u8) -> Vec<u8> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+
+    let midtone = |x: u8| low < x && x < high;
+
+    let mut values = img.pixels().map(|x| x.0[0] as f32).collect::<Vec<_>>();
+    let mut output = vec![0u8; width * height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let original = img.as_raw()[i];
+
+            if !midtone(original) {
+                output[i] = (original >= high) as u8;
+                continue;
+            }
+
+            let old = values[i];
+            let white = old >= 128.0;
+            output[i] = white as u8;
+
+            let error = old - if white { 255.0 } else { 0.0 };
+
+            // the error stays among the midtones, never bleeding into the solid areas
+            let mut spread = |x: Option<usize>, y: usize, weight: f32| {
+                if let Some(x) = x.filter(|&x| x < width) {
+                    if y < height && midtone(img.as_raw()[y * width + x]) {
+                        values[y * width + x] += error * weight;
+                    }
+                }
+            };
+
+            spread(Some(x + 1), y, 7.0 / 16.0);
+            spread(x.checked_sub(1), y + 1, 3.0 / 16.0);
+            spread(Some(x), y + 1, 5.0 / 16.0);
+            spread(Some(x + 1), y + 1, 1.0 / 16.0);
+        }
+    }
+
+    output
+}
+
+// every black pixel also blackens its four neighbours, thin lines come out a dot thicker
+// on each side for worn print heads that print single dots faintly
+pub fn embolden(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+
+    let black = |x: usize, y: usize| pixels[y * width + x] == 0;
+
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let dark = black(x, y)
+                || (x > 0 && black(x - 1, y))
+                || (x + 1 < width && black(x + 1, y))
+                || (y > 0 && black(x, y - 1))
+                || (y + 1 < height && black(x, y + 1));
+
+            !dark as u8
+        })
+        .collect()
+}
+
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+fn ordered(img: &GrayImage) -> Vec<u8> {
+    img.enumerate_pixels()
+        .map(|(x, y, pixel)| {
+            // spread the 64 matrix levels over 0..=255, centered in their bucket
+            let threshold = BAYER_8X8[y as usize % 8][x as usize % 8] * 4 + 2;
+
+            (pixel.0[0] > threshold) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // dark on the left, light on the right
+    fn gradient() -> GrayImage {
+        GrayImage::from_fn(16, 8, |x, _| image::Luma([(x * 17) as u8]))
+    }
+
+    // one row per line, `#` for black
+    fn pattern(pixels: &[u8]) -> Vec<String> {
+        pixels
+            .chunks(16)
+            .map(|row| {
+                row.iter()
+                    .map(|&x| if x == 0 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ordered_pattern() {
+        assert_eq!(
+            pattern(&ordered(&gradient())),
+            [
+                "####.#.#........",
+                "#####.#.#.#.....",
+                "##.#.#.#.#......",
+                "#######.#.#.#...",
+                "####.#.#........",
+                "#####.#.#.#.....",
+                "##.#.#.#.#......",
+                "#######.#.#.#...",
+            ]
+        );
+    }
+
+    #[test]
+    fn atkinson_pattern() {
+        assert_eq!(
+            pattern(&atkinson(&gradient())),
+            [
+                "#######..#......",
+                "#####.##........",
+                "#####..##.#.....",
+                "#######...#.....",
+                "####.##.##......",
+                "#####..#...#....",
+                "########..#.....",
+                "####.#..##......",
+            ]
+        );
+    }
+
+    #[test]
+    fn otsu_level_falls_between_the_peaks() {
+        // a dark peak around 40 and a bigger light one around 200
+        let img = GrayImage::from_fn(64, 64, |x, y| {
+            let spread = ((x + y) % 11) as u8;
+
+            if y < 24 {
+                image::Luma([35 + spread])
+            } else {
+                image::Luma([195 + spread])
+            }
+        });
+
+        let level = otsu_level(&img);
+        assert!((45..195).contains(&level), "{level}");
+
+        // every dark pixel black, every light one white
+        let pixels = threshold(&img, level);
+        assert!(pixels[..24 * 64].iter().all(|&x| x == 0));
+        assert!(pixels[24 * 64..].iter().all(|&x| x == 1));
+    }
+}
@@ -0,0 +1,568 @@
+use std::path::Path;
+
+use image::{GrayImage, Luma};
+
+use crate::error::PrinterBotError;
+use crate::{qr, text};
+
+const MAX_CANVAS_SIZE: i64 = 4000;
+const MAX_THICKNESS: i64 = 50;
+
+// one command per line, coordinates in pixels from the top left corner:
+//
+// canvas <width> <height>
+// line <x1> <y1> <x2> <y2> [thickness]
+// rect <x> <y> <width> <height> [thickness | fill]
+// text <x> <y> <size> <text...>
+// qr <x> <y> <width> <data...>
+pub fn render_drawing(spec: &str, font: &text::Font) -> Result<GrayImage, PrinterBotError> {
+    render(spec, font, None)
+}
+
+// a drawing with `{name}` placeholders, filled from `values`. a value is a single line, more
+// would be read as drawing commands of their own. templates can also place pictures,
+// `image <x> <y> <width> <path>` with the path relative to `dir` and at most `max_pixels` big
+pub fn render_template(
+    template: &str,
+    values: &[(String, String)],
+    font: &text::Font,
+    dir: &Path,
+    max_pixels: u64,
+) -> Result<GrayImage, PrinterBotError> {
+    let mut spec = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|x| start + x)
+            .ok_or(PrinterBotError::InvalidTemplate)?;
+        let name = &rest[start + 1..end];
+
+        let value = values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| PrinterBotError::MissingTemplateValue(name.to_string()))?;
+
+        if value.contains(['\n', '\r']) {
+            return Err(PrinterBotError::InvalidTemplateValue(name.to_string()));
+        }
+
+        spec.push_str(&rest[..start]);
+        spec.push_str(value);
+        rest = &rest[end + 1..];
+    }
+
+    spec.push_str(rest);
+
+    render(&spec, font, Some((dir, max_pixels)))
+}
+
+// pictures are only read from the template directory, never for drawings sent to the bot
+fn render(
+    spec: &str,
+    font: &text::Font,
+    pictures: Option<(&Path, u64)>,
+) -> Result<GrayImage, PrinterBotError> {
+    let mut img = GrayImage::from_pixel(720, 720, Luma([255]));
+
+    for (i, line) in spec.lines().enumerate() {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        let invalid = || PrinterBotError::InvalidDrawing(i + 1);
+        let number = |n: usize| {
+            words
+                .get(n)
+                .and_then(|x| x.parse::<i64>().ok())
+                .filter(|x| x.abs() <= MAX_CANVAS_SIZE)
+                .ok_or_else(invalid)
+        };
+
+        match words.first() {
+            None => {}
+            Some(&"canvas") => {
+                let (width, height) = (number(1)?, number(2)?);
+
+                if !(1..=MAX_CANVAS_SIZE).contains(&width)
+                    || !(1..=MAX_CANVAS_SIZE).contains(&height)
+                {
+                    return Err(invalid());
+                }
+
+                img = GrayImage::from_pixel(width as u32, height as u32, Luma([255]));
+            }
+            Some(&"line") => {
+                let thickness = number(5).unwrap_or(3).clamp(1, MAX_THICKNESS);
+
+                draw_line(
+                    &mut img,
+                    (number(1)?, number(2)?),
+                    (number(3)?, number(4)?),
+                    thickness,
+                );
+            }
+            Some(&"rect") => {
+                let (x, y, width, height) = (number(1)?, number(2)?, number(3)?, number(4)?);
+
+                if words.get(5) == Some(&"fill") {
+                    for py in y.max(0)..(y + height).min(img.height() as i64) {
+                        for px in x.max(0)..(x + width).min(img.width() as i64) {
+                            put_pixel(&mut img, px, py);
+                        }
+                    }
+                } else {
+                    let thickness = number(5).unwrap_or(3).clamp(1, MAX_THICKNESS);
+                    let (right, bottom) = (x + width, y + height);
+
+                    draw_line(&mut img, (x, y), (right, y), thickness);
+                    draw_line(&mut img, (right, y), (right, bottom), thickness);
+                    draw_line(&mut img, (right, bottom), (x, bottom), thickness);
+                    draw_line(&mut img, (x, bottom), (x, y), thickness);
+                }
+            }
+            Some(&"text") => {
+                let (x, y, size) = (number(1)?, number(2)?, number(3)?);
+                let content = words.get(4..).ok_or_else(invalid)?.join(" ");
+
+                if x < 0
+                    || y < 0
+                    || x >= img.width() as i64
+                    || !(1..=MAX_CANVAS_SIZE).contains(&size)
+                {
+                    return Err(invalid());
+                }
+
+                let label =
+                    text::render_text(font, &[&content], size as f32, img.width() - x as u32);
+
+                for (lx, ly, pixel) in label.enumerate_pixels() {
+                    if pixel.0[0] < 128 {
+                        put_pixel(&mut img, x + lx as i64, y + ly as i64);
+                    }
+                }
+            }
+            Some(&"qr") => {
+                let (x, y, width) = (number(1)?, number(2)?, number(3)?);
+                let data = words.get(4..).ok_or_else(invalid)?.join(" ");
+
+                if data.is_empty() || !(1..=MAX_CANVAS_SIZE).contains(&width) {
+                    return Err(invalid());
+                }
+
+                image::imageops::overlay(&mut img, &qr::render_qr(&data, width as u32)?, x, y);
+            }
+            Some(&"image") => {
+                let (x, y, width) = (number(1)?, number(2)?, number(3)?);
+                let path = words.get(4..).ok_or_else(invalid)?.join(" ");
+
+                let (dir, max_pixels) = pictures.ok_or_else(invalid)?;
+
+                if path.is_empty() || !(1..=MAX_CANVAS_SIZE).contains(&width) {
+                    return Err(invalid());
+                }
+
+                // only below dir, a template can come from someone else
+                let inside = Path::new(&path).components().all(|x| {
+                    matches!(
+                        x,
+                        std::path::Component::Normal(_) | std::path::Component::CurDir
+                    )
+                });
+
+                if !inside {
+                    return Err(invalid());
+                }
+
+                // only the header, before the picture is decoded
+                let path = dir.join(path);
+                let (picture_width, picture_height) = image::image_dimensions(&path)?;
+
+                if picture_width as u64 * picture_height as u64 > max_pixels {
+                    return Err(PrinterBotError::ImageTooLarge(
+                        picture_width,
+                        picture_height,
+                    ));
+                }
+
+                let picture = image::open(path)?.into_luma8();
+                let height = (picture.height() as u64 * width as u64
+                    / picture.width().max(1) as u64)
+                    .clamp(1, MAX_CANVAS_SIZE as u64) as u32;
+                let picture = image::imageops::resize(
+                    &picture,
+                    width as u32,
+                    height,
+                    image::imageops::FilterType::Lanczos3,
+                );
+
+                image::imageops::overlay(&mut img, &picture, x, y);
+            }
+            Some(_) => return Err(invalid()),
+        }
+    }
+
+    Ok(img)
+}
+
+// comma separated values, one row per line, as a ruled table as wide as `width` at most.
+// the text shrinks until the widest cell of every column fits
+pub fn render_table(
+    csv: &str,
+    font: &text::Font,
+    width: u32,
+) -> Result<GrayImage, PrinterBotError> {
+    const PADDING: u32 = 8;
+    const THICKNESS: i64 = 2;
+    const MAX_SIZE: f32 = 40.0;
+    const MIN_SIZE: f32 = 12.0;
+
+    let rows = csv
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(split_csv_line)
+        .collect::<Vec<_>>();
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+
+    if columns == 0 {
+        return Err(PrinterBotError::EmptyTable);
+    }
+
+    // widths at size 1, everything scales linearly with the size
+    let natural = (0..columns)
+        .map(|c| {
+            rows.iter()
+                .filter_map(|row| row.get(c))
+                .map(|cell| text::text_width(font, cell, 1.0))
+                .fold(0.0, f32::max)
+        })
+        .collect::<Vec<_>>();
+
+    let fixed = columns as u32 * 2 * PADDING + (columns as u32 + 1) * THICKNESS as u32;
+    let size = (width.saturating_sub(fixed) as f32 / natural.iter().sum::<f32>().max(1.0))
+        .clamp(MIN_SIZE, MAX_SIZE);
+
+    let column_widths = natural
+        .iter()
+        .map(|x| (x * size).ceil() as u32 + 2 * PADDING)
+        .collect::<Vec<_>>();
+
+    let render_cell = |cell: &str, column_width: u32| {
+        text::render_text(font, &[cell], size, column_width - 2 * PADDING)
+    };
+
+    let row_height = render_cell("", column_widths[0]).height() + 2 * PADDING;
+
+    let table_width =
+        (column_widths.iter().sum::<u32>() + (columns as u32 + 1) * THICKNESS as u32).min(width);
+    let table_height = rows.len() as u32 * row_height + (rows.len() as u32 + 1) * THICKNESS as u32;
+
+    let mut img = GrayImage::from_pixel(table_width, table_height, Luma([255]));
+
+    let mut y = THICKNESS;
+
+    for row in &rows {
+        let mut x = THICKNESS;
+
+        for (cell, &column_width) in row.iter().zip(&column_widths) {
+            let rendered = render_cell(cell, column_width);
+            image::imageops::overlay(&mut img, &rendered, x + PADDING as i64, y + PADDING as i64);
+
+            x += column_width as i64 + THICKNESS;
+        }
+
+        y += row_height as i64 + THICKNESS;
+    }
+
+    // the rules, the brush is centered so they are shifted by half of it
+    let (right, bottom) = (table_width as i64 - 1, table_height as i64 - 1);
+    let half = THICKNESS / 2;
+
+    for i in 0..=rows.len() as i64 {
+        let y = i * (row_height as i64 + THICKNESS) + half;
+        draw_line(&mut img, (0, y), (right, y), THICKNESS);
+    }
+
+    let mut x = half;
+    draw_line(&mut img, (x, 0), (x, bottom), THICKNESS);
+
+    for column_width in &column_widths {
+        x += *column_width as i64 + THICKNESS;
+        draw_line(&mut img, (x, 0), (x, bottom), THICKNESS);
+    }
+
+    Ok(img)
+}
+
+// fields can be quoted to contain commas, "" is a quote inside a quoted field
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+
+    fields.iter().map(|x| x.trim().to_string()).collect()
+}
+
+// the label and the percentage on top, a bar filled left to right under it
+pub fn render_progress_bar(font: &text::Font, percent: u8, label: &str, width: u32) -> GrayImage {
+    const MARGIN: i64 = 10;
+    const BAR_HEIGHT: i64 = 120;
+    const THICKNESS: i64 = 6;
+
+    let percent = percent.min(100);
+    let title = format!("{} {}%", label, percent);
+
+    let caption = text::render_text_block(
+        font,
+        &[text::TextLine {
+            text: title.trim(),
+            size: 60.0,
+            align: text::Align::Center,
+        }],
+        width,
+    );
+
+    let mut img = GrayImage::from_pixel(
+        width,
+        caption.height() + (BAR_HEIGHT + 2 * MARGIN) as u32,
+        Luma([255]),
+    );
+    image::imageops::overlay(&mut img, &caption, 0, 0);
+
+    let (left, right) = (MARGIN, width as i64 - 1 - MARGIN);
+    let top = caption.height() as i64 + MARGIN;
+    let bottom = top + BAR_HEIGHT - 1;
+
+    draw_line(&mut img, (left, top), (right, top), THICKNESS);
+    draw_line(&mut img, (right, top), (right, bottom), THICKNESS);
+    draw_line(&mut img, (right, bottom), (left, bottom), THICKNESS);
+    draw_line(&mut img, (left, bottom), (left, top), THICKNESS);
+
+    let filled = (right - left) * percent as i64 / 100;
+
+    for y in top..=bottom {
+        for x in left..=left + filled {
+            put_pixel(&mut img, x, y);
+        }
+    }
+
+    img
+}
+
+// the image, `gap` blank rows, then the image again upside down: folded in the middle of the
+// gap both halves read upright, for cable and gift tags
+pub fn add_fold_over_copy(img: &GrayImage, gap: u32) -> GrayImage {
+    let mut folded = GrayImage::from_pixel(img.width(), 2 * img.height() + gap, Luma([255]));
+
+    image::imageops::overlay(&mut folded, img, 0, 0);
+    image::imageops::overlay(
+        &mut folded,
+        &image::imageops::rotate180(img),
+        0,
+        (img.height() + gap) as i64,
+    );
+
+    folded
+}
+
+// a solid bar across the top, `height` rows tall, as an index tab on filing labels
+pub fn add_top_bar(img: &GrayImage, height: u32) -> GrayImage {
+    let mut barred = GrayImage::from_pixel(img.width(), img.height() + height, Luma([0]));
+    image::imageops::overlay(&mut barred, img, 0, height as i64);
+
+    barred
+}
+
+// pads the image above and below and marks its corners there with small Ls, lying on the
+// cut lines, for cutting continuous tape by hand or lining it up in a craft cutter. lines are
+// `vertical` times shorter at 600 dpi, so everything along the tape is that much longer
+pub fn add_registration_marks(img: &GrayImage, vertical: u32) -> GrayImage {
+    const MARGIN: u32 = 40;
+    const LENGTH: i64 = 24;
+    const THICKNESS: i64 = 3;
+
+    let margin = MARGIN * vertical;
+    let stretch = vertical as i64;
+
+    let mut marked = GrayImage::from_pixel(img.width(), img.height() + 2 * margin, Luma([255]));
+    image::imageops::overlay(&mut marked, img, 0, margin as i64);
+
+    let right = img.width() as i64 - 1;
+    let top = margin as i64 - 1;
+    let bottom = (margin + img.height()) as i64;
+
+    // corner, and which way the arms go from it
+    let corners = [
+        (0, top, 1, -1),
+        (right, top, -1, -1),
+        (0, bottom, 1, 1),
+        (right, bottom, -1, 1),
+    ];
+
+    for (x, y, dx, dy) in corners {
+        // across the tape
+        for i in 0..LENGTH {
+            for t in 0..THICKNESS * stretch {
+                put_pixel(&mut marked, x + i * dx, y + t * dy);
+            }
+        }
+
+        // along it
+        for i in 0..LENGTH * stretch {
+            for t in 0..THICKNESS {
+                put_pixel(&mut marked, x + t * dx, y + i * dy);
+            }
+        }
+    }
+
+    marked
+}
+
+fn put_pixel(img: &mut GrayImage, x: i64, y: i64) {
+    if x >= 0 && y >= 0 && x < img.width() as i64 && y < img.height() as i64 {
+        img.put_pixel(x as u32, y as u32, Luma([0]));
+    }
+}
+
+// bresenham, stamping a square brush `thickness` pixels wide at every step
+fn draw_line(img: &mut GrayImage, from: (i64, i64), to: (i64, i64), thickness: i64) {
+    let (mut x, mut y) = from;
+
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+
+    let mut err = dx + dy;
+
+    loop {
+        for by in 0..thickness {
+            for bx in 0..thickness {
+                put_pixel(img, x + bx - thickness / 2, y + by - thickness / 2);
+            }
+        }
+
+        if x == to.0 && y == to.1 {
+            break;
+        }
+
+        let e2 = 2 * err;
+
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // black pixels down the left edge, from the bottom of the top margin upwards
+    fn left_arm(marked: &GrayImage, margin: u32) -> u32 {
+        (0..margin)
+            .rev()
+            .take_while(|&y| marked.get_pixel(0, y).0[0] == 0)
+            .count() as u32
+    }
+
+    #[test]
+    fn registration_marks_keep_their_length_at_600_dpi() {
+        let img = GrayImage::from_pixel(100, 50, Luma([255]));
+
+        let marked = add_registration_marks(&img, 1);
+        assert_eq!(marked.height(), 50 + 2 * 40);
+        assert_eq!(left_arm(&marked, 40), 24);
+
+        let marked = add_registration_marks(&img, 2);
+        assert_eq!(marked.height(), 50 + 2 * 80);
+        assert_eq!(left_arm(&marked, 80), 48);
+    }
+
+    #[test]
+    fn template_pictures_stay_in_their_directory() {
+        let font = text::load_font(None, text::Edges::Smooth).unwrap();
+        let dir = std::env::temp_dir();
+
+        for path in ["../logo.png", "/etc/passwd", "logos/../../logo.png"] {
+            let template = format!("image 0 0 100 {path}");
+
+            assert!(
+                matches!(
+                    render_template(&template, &[], &font, &dir, u64::MAX),
+                    Err(PrinterBotError::InvalidDrawing(1))
+                ),
+                "{path}"
+            );
+        }
+
+        // a missing file below it is only an io error
+        assert!(matches!(
+            render_template(
+                "image 0 0 100 ./logos/missing.png",
+                &[],
+                &font,
+                &dir,
+                u64::MAX
+            ),
+            Err(PrinterBotError::Image(_))
+        ));
+    }
+
+    #[test]
+    fn template_values_are_one_line() {
+        let font = text::load_font(None, text::Edges::Smooth).unwrap();
+        let dir = std::env::temp_dir();
+
+        let values = [("name".to_string(), "Widget\nrect 0 0 720 720".to_string())];
+
+        assert!(matches!(
+            render_template("text 10 10 40 {name}", &values, &font, &dir, u64::MAX),
+            Err(PrinterBotError::InvalidTemplateValue(name)) if name == "name"
+        ));
+    }
+
+    #[test]
+    fn template_pictures_are_checked_before_decoding() {
+        let font = text::load_font(None, text::Edges::Smooth).unwrap();
+        let dir = std::env::temp_dir();
+
+        let name = format!("printer_bot_rs_picture_{}.png", std::process::id());
+        GrayImage::from_pixel(40, 30, Luma([0]))
+            .save(dir.join(&name))
+            .unwrap();
+
+        let template = format!("image 0 0 100 {name}");
+        let render = |max_pixels| render_template(&template, &[], &font, &dir, max_pixels);
+
+        let too_large = render(40 * 30 - 1);
+        let fits = render(40 * 30);
+        std::fs::remove_file(dir.join(&name)).unwrap();
+
+        assert!(matches!(
+            too_large,
+            Err(PrinterBotError::ImageTooLarge(40, 30))
+        ));
+        assert!(fits.is_ok());
+    }
+}
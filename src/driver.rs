@@ -1,8 +1,48 @@
 use std::{
     fs::File,
     io::{Read, Write},
+    net::TcpStream,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
 
+use log::*;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DriverError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("command sent out of order, job is {0:?}")]
+    InvalidSequence(JobPhase),
+    #[error("printer is in use by another host")]
+    PrinterInUse,
+    #[error("printer not ready: {0}")]
+    NotReady(&'static str),
+    #[error("unexpected {0} in the status reply: {1:#04x}")]
+    InvalidStatus(&'static str, u8),
+    #[error("print too long for the media: {lines} lines, at most {max}")]
+    PrintTooLong { lines: usize, max: u32 },
+    #[error("print cancelled")]
+    Cancelled,
+}
+
+impl DriverError {
+    // worth resetting the printer and sending the job again, unlike media or cover problems
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DriverError::Io(_))
+    }
+}
+
+// how the raw bytes get to the printer, the commands on top are the same for all of them
+pub trait PrinterTransport {
+    fn read(&mut self, length: usize) -> Result<Vec<u8>, std::io::Error>;
+    fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error>;
+    // like read, but returns right away with None when the printer has nothing to say
+    fn try_read(&mut self, length: usize) -> Result<Option<Vec<u8>>, std::io::Error>;
+}
+
+// the lp device node of the usblp kernel driver
 pub struct Printer {
     fd: std::fs::File,
 }
@@ -13,8 +53,10 @@ impl Printer {
 
         Ok(Self { fd })
     }
+}
 
-    pub fn read(&mut self, length: usize) -> Result<Vec<u8>, std::io::Error> {
+impl PrinterTransport for Printer {
+    fn read(&mut self, length: usize) -> Result<Vec<u8>, std::io::Error> {
         let mut buf = vec![0u8; length];
 
         let mut tries = 0;
@@ -31,13 +73,124 @@ impl Printer {
         Ok(buf)
     }
 
-    pub fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+    fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
         self.fd.write_all(data)?;
         Ok(())
     }
+
+    // usblp returns no bytes at all when nothing is pending
+    fn try_read(&mut self, length: usize) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let mut buf = vec![0u8; length];
+
+        let read = self.fd.read(&mut buf)?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        if read < length {
+            buf[read..].copy_from_slice(&self.read(length - read)?);
+        }
+
+        Ok(Some(buf))
+    }
+}
+
+// a network printer on its raw printing port, 9100 on the QL-720NW and QL-8xx
+pub struct TcpPrinter {
+    stream: TcpStream,
+}
+
+impl TcpPrinter {
+    pub fn connect(address: &str) -> Result<Self, std::io::Error> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+        Ok(Self { stream })
+    }
 }
 
-#[derive(Debug)]
+impl PrinterTransport for TcpPrinter {
+    fn read(&mut self, length: usize) -> Result<Vec<u8>, std::io::Error> {
+        let mut buf = vec![0u8; length];
+
+        self.stream.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        self.stream.write_all(data)
+    }
+
+    fn try_read(&mut self, length: usize) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let mut buf = vec![0u8; length];
+
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+
+        let read = match read {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+            // the printer closed the connection
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            read => read?,
+        };
+
+        if read < length {
+            buf[read..].copy_from_slice(&self.read(length - read)?);
+        }
+
+        Ok(Some(buf))
+    }
+}
+
+// which fields of the print information the printer checks against the loaded media
+#[derive(Debug, Clone, Copy)]
+pub struct PrintInfoFlags {
+    pub kind: bool,
+    pub width: bool,
+    pub length: bool,
+    // prefer print quality over speed
+    pub quality: bool,
+    // resume printing after an error is cleared
+    pub recover: bool,
+}
+
+impl PrintInfoFlags {
+    const KIND: u8 = 0x02;
+    const WIDTH: u8 = 0x04;
+    const LENGTH: u8 = 0x08;
+    const QUALITY: u8 = 0x40;
+    const RECOVER: u8 = 0x80;
+
+    fn to_bits(self) -> u8 {
+        [
+            (self.kind, Self::KIND),
+            (self.width, Self::WIDTH),
+            (self.length, Self::LENGTH),
+            (self.quality, Self::QUALITY),
+            (self.recover, Self::RECOVER),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |bits, (_, bit)| bits | bit)
+    }
+}
+
+impl Default for PrintInfoFlags {
+    fn default() -> Self {
+        PrintInfoFlags {
+            kind: true,
+            width: true,
+            length: true,
+            quality: true,
+            recover: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 struct ErrorInformation1 {
     no_media_when_printing: bool,
     end_of_media: bool,
@@ -63,7 +216,7 @@ impl ErrorInformation1 {
         }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct ErrorInformation2 {
     transmission_error: bool,
     cover_opened_while_printing: bool,
@@ -87,13 +240,13 @@ impl ErrorInformation2 {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MediaType {
     NoMedia = 0x00,
     Continuous = 0x0A,
     DieCutLabels = 0x0B,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum StatusType {
     ReplyToStatusRequest,
     PrintingCompleted,
@@ -102,14 +255,190 @@ pub enum StatusType {
     PhaseChange,
 }
 
-#[derive(Debug)]
+// byte 22, only meaningful when the status type is a notification
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Notification {
+    NotAvailable,
+    CoolingStarted,
+    CoolingFinished,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CommandMode {
+    Raster = 0x01,
+    PtouchTemplate = 0x03,
+}
+
+// model code from byte 4 of the status reply
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrinterModel {
+    Ql500,
+    Ql560,
+    Ql570,
+    Ql580N,
+    Ql650Td,
+    Ql700,
+    Ql710W,
+    Ql720Nw,
+    Ql800,
+    Ql810W,
+    Ql820Nwb,
+    Ql1050,
+    Ql1060N,
+    Ql1100,
+    Ql1110Nwb,
+    Ql1115Nwb,
+    Unknown(u8),
+}
+
+impl PrinterModel {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0x4F => PrinterModel::Ql500,
+            0x31 => PrinterModel::Ql560,
+            0x32 => PrinterModel::Ql570,
+            0x33 => PrinterModel::Ql580N,
+            0x51 => PrinterModel::Ql650Td,
+            0x35 => PrinterModel::Ql700,
+            0x36 => PrinterModel::Ql710W,
+            0x37 => PrinterModel::Ql720Nw,
+            0x38 => PrinterModel::Ql800,
+            0x39 => PrinterModel::Ql810W,
+            0x41 => PrinterModel::Ql820Nwb,
+            0x50 => PrinterModel::Ql1050,
+            0x34 => PrinterModel::Ql1060N,
+            0x43 => PrinterModel::Ql1100,
+            0x44 => PrinterModel::Ql1110Nwb,
+            0x45 => PrinterModel::Ql1115Nwb,
+            code => PrinterModel::Unknown(code),
+        }
+    }
+
+    // the 102mm models have a 1296 dot head instead of 720
+    fn is_wide(&self) -> bool {
+        matches!(
+            self,
+            PrinterModel::Ql1050
+                | PrinterModel::Ql1060N
+                | PrinterModel::Ql1100
+                | PrinterModel::Ql1110Nwb
+                | PrinterModel::Ql1115Nwb
+        )
+    }
+
+    // bytes in every raster line sent to this model
+    pub fn line_bytes(&self) -> usize {
+        if self.is_wide() {
+            162
+        } else {
+            90
+        }
+    }
+
+    // tiff packbits raster lines, the older models only take them uncompressed
+    pub fn supports_compression(&self) -> bool {
+        !matches!(
+            self,
+            PrinterModel::Ql500
+                | PrinterModel::Ql560
+                | PrinterModel::Ql650Td
+                | PrinterModel::Ql1050
+                | PrinterModel::Ql1060N
+                | PrinterModel::Unknown(_)
+        )
+    }
+
+    // P-touch Template mode, prints the templates stored on the printer with P-touch Editor.
+    // only the models with a template memory have it
+    pub fn supports_templates(&self) -> bool {
+        matches!(
+            self,
+            PrinterModel::Ql580N | PrinterModel::Ql1050 | PrinterModel::Ql1060N
+        )
+    }
+
+    // 300x600 dpi printing, older models ignore the flag and print at half the length
+    pub fn supports_high_resolution(&self) -> bool {
+        !matches!(
+            self,
+            PrinterModel::Ql500
+                | PrinterModel::Ql560
+                | PrinterModel::Ql650Td
+                | PrinterModel::Unknown(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CutMode {
+    EveryLabel,
+    AtEnd,
+    // for banners on continuous tape, cut by hand
+    Never,
+}
+
+#[derive(Debug, Clone)]
 pub enum PhaseState {
     Waiting,
     Printing,
 }
 
-#[derive(Debug)]
+// width and length in mm (0 for continuous), printable dots, dots before the printable
+// area, printable lines (0 for continuous)
+const MEDIA: &[(u8, u8, u16, u16, u32)] = &[
+    (12, 0, 106, 29, 0),
+    (29, 0, 306, 6, 0),
+    (38, 0, 413, 12, 0),
+    (50, 0, 554, 12, 0),
+    (54, 0, 590, 0, 0),
+    (62, 0, 696, 12, 0),
+    (102, 0, 1164, 12, 0),
+    (17, 54, 165, 0, 566),
+    (17, 87, 165, 0, 956),
+    (23, 23, 202, 42, 202),
+    (29, 42, 306, 6, 425),
+    (29, 90, 306, 6, 991),
+    (38, 90, 413, 12, 991),
+    (39, 48, 425, 6, 495),
+    (52, 29, 578, 0, 271),
+    (62, 29, 696, 12, 271),
+    (62, 100, 696, 12, 1109),
+    (102, 51, 1164, 12, 526),
+    (102, 152, 1164, 12, 1660),
+    // DK-11247, sold as 103x164 and reported 104mm wide. wide models only
+    (104, 164, 1200, 12, 1910),
+    (12, 12, 94, 113, 94),
+    (24, 24, 236, 42, 236),
+    (58, 58, 618, 51, 618),
+];
+
+// a tape or label size, length 0 for continuous tape
+#[derive(Debug, Clone, Copy)]
+pub struct Media {
+    pub width: u8,
+    pub length: u8,
+}
+
+impl Media {
+    pub fn is_known(&self) -> bool {
+        MEDIA
+            .iter()
+            .any(|x| x.0 == self.width && x.1 == self.length)
+    }
+
+    // dots across the media, none when it isn't known
+    pub fn printable_dots(&self) -> Option<u16> {
+        MEDIA
+            .iter()
+            .find(|x| x.0 == self.width && x.1 == self.length)
+            .map(|x| x.2)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PrinterStatus {
+    model: PrinterModel,
     media_width: u8,
     media_length: u8,
     media_type: MediaType,
@@ -117,85 +446,306 @@ pub struct PrinterStatus {
     error2: ErrorInformation2,
     status_type: StatusType,
     phase_state: PhaseState,
+    notification: Notification,
+}
+
+impl PrinterStatus {
+    pub fn model(&self) -> PrinterModel {
+        self.model
+    }
+
+    pub fn notification(&self) -> Notification {
+        match self.status_type {
+            StatusType::Notification => self.notification,
+            _ => Notification::NotAvailable,
+        }
+    }
+
+    // first and last printable dot of the raster line for the loaded media,
+    // none when the media isn't known
+    pub fn printable_range(&self) -> Option<(u16, u16)> {
+        // the wide models center the same media on their longer head
+        let model_offset = if self.model.is_wide() { 44 } else { 0 };
+
+        self.media().map(|&(_, _, dots, offset, _)| {
+            let offset = model_offset + offset;
+            (offset, offset + dots - 1)
+        })
+    }
+
+    // the most raster lines that fit on the loaded media at 300 dpi, none when it isn't known
+    pub fn max_lines(&self) -> Option<u32> {
+        // continuous tape is limited by the printer, to a meter
+        const CONTINUOUS_MAX_LINES: u32 = 11811;
+
+        self.media()
+            .map(|&(_, _, _, _, lines)| match self.media_type {
+                MediaType::Continuous => CONTINUOUS_MAX_LINES,
+                _ => lines,
+            })
+    }
+
+    fn media(&self) -> Option<&'static (u8, u8, u16, u16, u32)> {
+        let length = match self.media_type {
+            MediaType::NoMedia => return None,
+            MediaType::Continuous => 0,
+            MediaType::DieCutLabels => self.media_length,
+        };
+
+        MEDIA
+            .iter()
+            .find(|x| x.0 == self.media_width && x.1 == length)
+    }
+
+    // for tapes that report the wrong size, everything after uses `media` instead
+    pub fn with_media(self, media: Media) -> Self {
+        Self {
+            media_width: media.width,
+            media_length: media.length,
+            media_type: if media.length == 0 {
+                MediaType::Continuous
+            } else {
+                MediaType::DieCutLabels
+            },
+            ..self
+        }
+    }
+
+    // feed before the cut in dots: die-cut labels are cut at the gap and need none,
+    // continuous tape gets the 3mm the raster reference asks for
+    pub fn default_margin(&self) -> u16 {
+        match self.media_type {
+            MediaType::Continuous => 35,
+            MediaType::DieCutLabels | MediaType::NoMedia => 0,
+        }
+    }
+
+    // everything that has to be fine before a job can be sent
+    pub fn is_ready(&self) -> Result<(), DriverError> {
+        // another host is streaming to the printer, don't interleave our job with theirs
+        if self.error1.main_unit_in_use {
+            return Err(DriverError::PrinterInUse);
+        }
+
+        if let Some(problem) = self.error() {
+            return Err(DriverError::NotReady(problem));
+        }
+
+        if matches!(self.media_type, MediaType::NoMedia) {
+            return Err(DriverError::NotReady("no media loaded"));
+        }
+
+        if matches!(self.phase_state, PhaseState::Printing) {
+            return Err(DriverError::NotReady("printer is busy printing"));
+        }
+
+        Ok(())
+    }
+
+    // tape width in mm as reported, or as set by with_media
+    pub fn media_width_mm(&self) -> u8 {
+        self.media_width
+    }
+
+    // label length in mm, 0 for continuous tape
+    pub fn media_length_mm(&self) -> u8 {
+        self.media_length
+    }
+
+    pub fn media_type(&self) -> MediaType {
+        self.media_type
+    }
+
+    // the first of errors, what a job is stopped for
+    pub fn error(&self) -> Option<&'static str> {
+        self.errors().first().copied()
+    }
+
+    // every error flag that is set, also meaningful in the middle of a job unlike the rest
+    // of is_ready
+    pub fn errors(&self) -> Vec<&'static str> {
+        let flags = [
+            (self.error1.no_media_when_printing, "no media when printing"),
+            (self.error1.end_of_media, "end of media"),
+            (self.error1.tape_cutter_jam, "tape cutter jam"),
+            (self.error1.fan_doesnt_work, "fan doesn't work"),
+            (self.error2.transmission_error, "transmission error"),
+            (
+                self.error2.cover_opened_while_printing,
+                "cover opened while printing",
+            ),
+            (self.error2.cannot_feed, "cannot feed"),
+            (self.error2.system_error, "system error"),
+        ];
+
+        let mut errors = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, problem)| *problem)
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() && matches!(self.status_type, StatusType::Error) {
+            errors.push("printer reported an error");
+        }
+
+        errors
+    }
 }
+
+// where we are in the print job, raster data is only accepted after the print information
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobPhase {
+    Idle,
+    Configured,
+    Rastering,
+    Printed,
+}
+
+// how PrinterCommander::print_lines sends a job
+#[derive(Debug, Clone, Copy)]
+pub struct PrintOptions<'a> {
+    // every copy is a page of the same job, cut as labels are
+    pub copies: u32,
+    pub cut_mode: CutMode,
+    pub high_resolution: bool,
+    // the media's default_margin when not set
+    pub feed_margin: Option<u16>,
+    // ignored on models without supports_compression
+    pub compression: bool,
+    pub print_info_flags: PrintInfoFlags,
+    // waited before the last page, some printers cut before the last lines have fed through
+    pub cut_delay: Option<Duration>,
+    // every how many lines to look for an error the printer sent on its own
+    pub status_check_lines: Option<usize>,
+    // blank lines as a single byte each, at most `max_zero_raster_run` in a row
+    pub zero_raster_graphics: bool,
+    pub max_zero_raster_run: usize,
+    // set from another thread to drop the job before the next line
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl Default for PrintOptions<'_> {
+    fn default() -> Self {
+        PrintOptions {
+            copies: 1,
+            cut_mode: CutMode::EveryLabel,
+            high_resolution: false,
+            feed_margin: None,
+            compression: false,
+            print_info_flags: PrintInfoFlags::default(),
+            cut_delay: None,
+            status_check_lines: None,
+            zero_raster_graphics: false,
+            max_zero_raster_run: usize::MAX,
+            cancel: None,
+        }
+    }
+}
+
 pub struct PrinterCommander {
-    printer: Printer,
+    printer: Box<dyn PrinterTransport>,
+    phase: JobPhase,
+    compression: bool,
 }
 
 impl PrinterCommander {
-    pub fn main(path: &str) -> Result<Self, std::io::Error> {
+    pub fn main(path: &str) -> Result<Self, DriverError> {
         let lp = Printer::new(path)?;
 
-        Ok(Self { printer: lp })
+        Ok(Self::new(Box::new(lp)))
     }
 
-    pub fn reset(&mut self) -> Result<(), std::io::Error> {
-        self.printer.write(&[0x00; 200])
+    pub fn new(printer: Box<dyn PrinterTransport>) -> Self {
+        Self {
+            printer,
+            phase: JobPhase::Idle,
+            compression: false,
+        }
     }
 
-    pub fn initilize(&mut self) -> Result<(), std::io::Error> {
-        self.printer.write(&[0x1b, 0x40])
+    fn expect_phase(&self, allowed: &[JobPhase]) -> Result<(), DriverError> {
+        if allowed.contains(&self.phase) {
+            Ok(())
+        } else {
+            Err(DriverError::InvalidSequence(self.phase))
+        }
     }
 
-    pub fn get_status(&mut self) -> Result<(), std::io::Error> {
-        self.printer.write(&[0x1b, 0x69, 0x53])
+    // bypasses the job phase checks, the caller is responsible for sending a valid sequence
+    pub fn send_command_unchecked(&mut self, command: &[u8]) -> Result<(), DriverError> {
+        Ok(self.printer.write(command)?)
     }
 
-    pub fn set_raster_mode(&mut self) -> Result<(), std::io::Error> {
-        self.printer.write(&[0x1b, 0x69, 0x61, 0x01])
+    pub fn reset(&mut self) -> Result<(), DriverError> {
+        self.printer.write(&[0x00; 200])?;
+        self.phase = JobPhase::Idle;
+        Ok(())
     }
 
-    pub fn read_status(&mut self) -> Result<PrinterStatus, std::io::Error> {
-        let res = self.printer.read(32)?;
-        assert!(res[0] == 0x80);
-        assert!(res[1] == 0x20);
+    pub fn initilize(&mut self) -> Result<(), DriverError> {
+        self.printer.write(&[0x1b, 0x40])?;
+        self.phase = JobPhase::Idle;
+        self.compression = false;
+        Ok(())
+    }
 
-        let media_type = match res[11] {
-            0x00 => MediaType::NoMedia,
-            0x0A => MediaType::Continuous,
-            0x0B => MediaType::DieCutLabels,
-            _ => panic!("Unknown media type"),
-        };
+    pub fn get_status(&mut self) -> Result<(), DriverError> {
+        Ok(self.printer.write(&[0x1b, 0x69, 0x53])?)
+    }
 
-        let status_type = match res[18] {
-            0x00 => StatusType::ReplyToStatusRequest,
-            0x01 => StatusType::PrintingCompleted,
-            0x02 => StatusType::Error,
-            0x05 => StatusType::Notification,
-            0x06 => StatusType::PhaseChange,
-            _ => panic!("Unknown status type"),
-        };
+    pub fn set_command_mode(&mut self, mode: CommandMode) -> Result<(), DriverError> {
+        Ok(self.printer.write(&[0x1b, 0x69, 0x61, mode as u8])?)
+    }
 
-        let phase_state = match res[19] {
-            0x00 => PhaseState::Waiting,
-            0x01 => PhaseState::Printing,
-            _ => panic!("Unknown phase state"),
-        };
+    pub fn set_raster_mode(&mut self) -> Result<(), DriverError> {
+        self.set_command_mode(CommandMode::Raster)
+    }
 
-        Ok(PrinterStatus {
-            media_width: res[10],
-            media_type,
-            media_length: res[17],
-            error1: ErrorInformation1::from_bits(res[8]),
-            error2: ErrorInformation2::from_bits(res[9]),
-            status_type,
-            phase_state,
-        })
+    // P-touch Template mode only (QL-580N/1050/1060N), picks one of the templates stored on the printer
+    pub fn select_template(&mut self, number: u8) -> Result<(), DriverError> {
+        Ok(self.printer.write(format!("^TS{number:03}").as_bytes())?)
+    }
+
+    // P-touch Template mode only, prints the selected template
+    pub fn print_template(&mut self) -> Result<(), DriverError> {
+        Ok(self.printer.write(b"^FF")?)
+    }
+
+    // the 32 bytes of the status reply, as they came
+    pub fn read_status_raw(&mut self) -> Result<Vec<u8>, DriverError> {
+        Ok(self.printer.read(32)?)
+    }
+
+    pub fn read_status(&mut self) -> Result<PrinterStatus, DriverError> {
+        let res = self.read_status_raw()?;
+
+        parse_status(&res)
     }
 
-    // pag 20
+    // a status the printer sent on its own, e.g. an error in the middle of a job,
+    // without waiting for one when there is none
+    pub fn poll_status(&mut self) -> Result<Option<PrinterStatus>, DriverError> {
+        self.printer
+            .try_read(32)?
+            .map(|x| parse_status(&x))
+            .transpose()
+    }
+
+    // pag 20, sent again before every page of a job
     pub fn set_print_inforomation(
         &mut self,
-        status: PrinterStatus,
+        status: &PrinterStatus,
         line_count: u32,
-    ) -> Result<(), std::io::Error> {
-        const FLAGS: u8 = 0x02 | 0x04 | 0x08 | 0x40 | 0x80;
+        flags: PrintInfoFlags,
+        starting_page: bool,
+    ) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Idle, JobPhase::Printed])?;
 
         let mut set_print_info_command = [
             0x1b,
             0x69,
             0x7a,
-            FLAGS,
+            flags.to_bits(),
             status.media_type as u8,
             status.media_width,
             status.media_length,
@@ -203,38 +753,762 @@ impl PrinterCommander {
             0,
             0,
             0,
-            1,
+            if starting_page { 0 } else { 1 },
             0,
         ];
 
         set_print_info_command[7..11].copy_from_slice(&line_count.to_le_bytes());
 
-        self.printer.write(&set_print_info_command)
+        self.printer.write(&set_print_info_command)?;
+        self.phase = JobPhase::Configured;
+        Ok(())
+    }
+
+    // various and expanded mode, has to be sent after the print information
+    pub fn set_modes(
+        &mut self,
+        cut_mode: CutMode,
+        high_resolution: bool,
+    ) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Configured])?;
+
+        const AUTO_CUT: u8 = 0x40;
+        const CUT_AT_END: u8 = 0x08;
+        const HIGH_RESOLUTION: u8 = 0x40;
+
+        let (various_mode, mut expanded_mode) = match cut_mode {
+            CutMode::EveryLabel => (AUTO_CUT, CUT_AT_END),
+            CutMode::AtEnd => (0, CUT_AT_END),
+            CutMode::Never => (0, 0),
+        };
+
+        if high_resolution {
+            expanded_mode |= HIGH_RESOLUTION;
+        }
+
+        self.printer.write(&[0x1b, 0x69, 0x4d, various_mode])?;
+
+        if let CutMode::EveryLabel = cut_mode {
+            // cut after every single label
+            self.printer.write(&[0x1b, 0x69, 0x41, 1])?;
+        }
+
+        Ok(self.printer.write(&[0x1b, 0x69, 0x4b, expanded_mode])?)
     }
 
-    pub fn set_margin_amount(&mut self, margin: u16) -> Result<(), std::io::Error> {
+    pub fn set_margin_amount(&mut self, margin: u16) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Idle, JobPhase::Configured])?;
+
         let mut set_margin_amount_command = [0x1b, 0x69, 0x64, 0x00, 0x00];
 
         set_margin_amount_command[3..5].copy_from_slice(&margin.to_le_bytes());
 
-        self.printer.write(&set_margin_amount_command)
+        Ok(self.printer.write(&set_margin_amount_command)?)
     }
 
-    pub fn raster_line(&mut self, line: &[u8; 90]) -> Result<(), std::io::Error> {
-        const LINE_LENGTH: u8 = 90;
+    // the raster lines that follow are packed, only for models that supports_compression
+    pub fn set_compression(&mut self, enabled: bool) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Idle, JobPhase::Configured])?;
+
+        self.printer
+            .write(&[0x4d, if enabled { 0x02 } else { 0x00 }])?;
+        self.compression = enabled;
+        Ok(())
+    }
+
+    // `line` has to be PrinterModel::line_bytes long
+    pub fn raster_line(&mut self, line: &[u8]) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Configured, JobPhase::Rastering])?;
+
+        let line = if self.compression {
+            pack_bits(line)
+        } else {
+            line.to_vec()
+        };
 
-        let mut command = vec![0x67, 0x00, LINE_LENGTH];
-        command.extend_from_slice(line);
+        assert!(line.len() <= u8::MAX as usize);
 
-        assert!(line.len() == LINE_LENGTH as usize);
+        let mut command = vec![0x67, 0x00, line.len() as u8];
+        command.extend_from_slice(&line);
 
-        self.printer.write(&command)
+        self.printer.write(&command)?;
+        self.phase = JobPhase::Rastering;
+        Ok(())
+    }
+
+    // a blank line in a single byte, instead of 93
+    pub fn zero_raster_line(&mut self) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Configured, JobPhase::Rastering])?;
+
+        self.printer.write(&[0x5a])?;
+        self.phase = JobPhase::Rastering;
+        Ok(())
+    }
+
+    // prints the current page, the next one starts with its print information
+    pub fn print(&mut self) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Rastering])?;
+
+        self.printer.write(&[0x0c])?;
+        self.phase = JobPhase::Printed;
+        Ok(())
     }
-    pub fn print(&mut self) -> Result<(), std::io::Error> {
-        self.printer.write(&[0x0c])
+
+    pub fn print_last_page(&mut self) -> Result<(), DriverError> {
+        self.expect_phase(&[JobPhase::Rastering])?;
+
+        self.printer.write(&[0x1A])?;
+        self.phase = JobPhase::Printed;
+        Ok(())
+    }
+
+    // a whole job, from raster mode to the last page. the lines are sent as they come, only
+    // their number has to be known up front, and `lines` is cloned to start every copy over
+    pub fn print_lines(
+        &mut self,
+        status: &PrinterStatus,
+        count: usize,
+        lines: impl Iterator<Item = Vec<u8>> + Clone,
+        options: &PrintOptions,
+    ) -> Result<(), DriverError> {
+        // checked before sending anything, the printer would only fail halfway through
+        if let Some(max) = status.max_lines() {
+            let max = if options.high_resolution {
+                2 * max
+            } else {
+                max
+            };
+
+            if count > max as usize {
+                return Err(DriverError::PrintTooLong { lines: count, max });
+            }
+        }
+
+        self.set_raster_mode()?;
+
+        let margin = options
+            .feed_margin
+            .unwrap_or_else(|| status.default_margin());
+
+        let compression = options.compression && status.model().supports_compression();
+
+        debug!("printing {} lines, {} copies", count, options.copies);
+
+        for copy in 0..options.copies {
+            if copy > 0 {
+                self.print()?;
+            }
+
+            self.set_print_inforomation(status, count as u32, options.print_info_flags, copy == 0)?;
+
+            if copy == 0 {
+                self.set_modes(options.cut_mode, options.high_resolution)?;
+
+                self.set_margin_amount(margin)?;
+
+                if compression {
+                    self.set_compression(true)?;
+                }
+            }
+
+            self.send_page(count, lines.clone(), options)?;
+        }
+
+        if let Some(delay) = options.cut_delay {
+            std::thread::sleep(delay);
+        }
+
+        self.print_last_page()?;
+
+        for _ in 0..3 {
+            let status = self.read_status()?;
+            trace!("{:#?}", status);
+
+            // long jobs overheat the head, the printer pauses by itself until it cools down
+            match status.notification() {
+                Notification::CoolingStarted => warn!("printer cooling down, pausing"),
+                Notification::CoolingFinished => info!("printer cooled down, resuming"),
+                Notification::Unknown(code) => debug!("unknown notification {:#04x}", code),
+                Notification::NotAvailable => {}
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn print_last_page(&mut self) -> Result<(), std::io::Error> {
-        self.printer.write(&[0x1A])
+    // the raster lines of one page, the caller sends the print command after it
+    fn send_page(
+        &mut self,
+        count: usize,
+        lines: impl Iterator<Item = Vec<u8>>,
+        options: &PrintOptions,
+    ) -> Result<(), DriverError> {
+        // some firmwares choke on long runs of zero lines, so a full line is sent every so often
+        let mut zero_run = 0;
+
+        for (i, line) in lines.take(count).enumerate() {
+            // the invalidate and initialize commands drop the half sent page
+            if options
+                .cancel
+                .is_some_and(|x| x.swap(false, Ordering::Relaxed))
+            {
+                warn!("print cancelled after {} of {} lines", i, count);
+
+                self.reset()?;
+                self.initilize()?;
+
+                return Err(DriverError::Cancelled);
+            }
+
+            // an error in the middle of the job comes unasked, left unread it would be taken for
+            // the reply to a later status request
+            if options
+                .status_check_lines
+                .is_some_and(|n| i > 0 && i % n == 0)
+            {
+                if let Some(status) = self.poll_status()? {
+                    trace!("{:#?}", status);
+
+                    if let Some(problem) = status.error() {
+                        warn!("printer reported an error after {} lines, {}", i, problem);
+                        return Err(DriverError::NotReady(problem));
+                    }
+                }
+            }
+
+            let is_blank = line.iter().all(|&x| x == 0);
+
+            if options.zero_raster_graphics && is_blank && zero_run < options.max_zero_raster_run {
+                self.zero_raster_line()?;
+                zero_run += 1;
+            } else {
+                self.raster_line(&line)?;
+                zero_run = 0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// tiff packbits: a header byte n, then n + 1 literal bytes if n < 128, or one byte repeated
+// 257 - n times. a 162 byte line never packs to more than 164
+fn pack_bits(data: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(128)
+            .take_while(|&&x| x == data[i])
+            .count();
+
+        // a run of two packs no smaller than the literal bytes
+        if run > 2 {
+            packed.push((257 - run) as u8);
+            packed.push(data[i]);
+            i += run;
+            continue;
+        }
+
+        // literal bytes, up to where the next run starts
+        let start = i;
+
+        while i < data.len() && i - start < 128 && !data[i..].starts_with(&[data[i]; 3]) {
+            i += 1;
+        }
+
+        packed.push((i - start - 1) as u8);
+        packed.extend_from_slice(&data[start..i]);
+    }
+
+    packed
+}
+
+// never panics, whatever the printer sends
+fn parse_status(res: &[u8]) -> Result<PrinterStatus, DriverError> {
+    if res.len() < 32 {
+        return Err(DriverError::InvalidStatus("reply length", res.len() as u8));
+    }
+
+    // print head mark and reply size, the same on every QL model. some firmwares are said to
+    // differ, the rest of the reply is still worth reading then
+    if res[..2] != [0x80, 0x20] {
+        warn!(
+            "unexpected status header {:02x?}, model {:02x}",
+            &res[..2],
+            res[4]
+        );
+    }
+
+    let media_type = match res[11] {
+        0x00 => MediaType::NoMedia,
+        0x0A => MediaType::Continuous,
+        0x0B => MediaType::DieCutLabels,
+        other => return Err(DriverError::InvalidStatus("media type", other)),
+    };
+
+    let status_type = match res[18] {
+        0x00 => StatusType::ReplyToStatusRequest,
+        0x01 => StatusType::PrintingCompleted,
+        0x02 => StatusType::Error,
+        0x05 => StatusType::Notification,
+        0x06 => StatusType::PhaseChange,
+        other => return Err(DriverError::InvalidStatus("status type", other)),
+    };
+
+    let phase_state = match res[19] {
+        0x00 => PhaseState::Waiting,
+        0x01 => PhaseState::Printing,
+        other => return Err(DriverError::InvalidStatus("phase state", other)),
+    };
+
+    Ok(PrinterStatus {
+        model: PrinterModel::from_code(res[4]),
+        media_width: res[10],
+        media_type,
+        media_length: res[17],
+        error1: ErrorInformation1::from_bits(res[8]),
+        error2: ErrorInformation2::from_bits(res[9]),
+        status_type,
+        phase_state,
+        notification: match res[22] {
+            0x00 => Notification::NotAvailable,
+            0x03 => Notification::CoolingStarted,
+            0x04 => Notification::CoolingFinished,
+            other => Notification::Unknown(other),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a status reply with nothing wrong, for the model code and media
+    fn reply(model: u8, width: u8, length: u8, media_type: MediaType) -> Vec<u8> {
+        let mut reply = vec![0; 32];
+        reply[..2].copy_from_slice(&[0x80, 0x20]);
+        reply[4] = model;
+        reply[10] = width;
+        reply[11] = media_type as u8;
+        reply[17] = length;
+
+        reply
+    }
+
+    const QL_500: u8 = 0x4F;
+    const QL_1050: u8 = 0x50;
+
+    // everything written, in one piece
+    struct Recorder(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl PrinterTransport for Recorder {
+        fn read(&mut self, _length: usize) -> Result<Vec<u8>, std::io::Error> {
+            Err(std::io::ErrorKind::UnexpectedEof.into())
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+            self.0.borrow_mut().extend_from_slice(data);
+            Ok(())
+        }
+
+        fn try_read(&mut self, _length: usize) -> Result<Option<Vec<u8>>, std::io::Error> {
+            Ok(None)
+        }
+    }
+
+    fn recording_printer() -> (PrinterCommander, std::rc::Rc<std::cell::RefCell<Vec<u8>>>) {
+        let written = std::rc::Rc::default();
+        let printer = PrinterCommander::new(Box::new(Recorder(std::rc::Rc::clone(&written))));
+
+        (printer, written)
+    }
+
+    #[test]
+    fn unchecked_commands_skip_the_job_phase() {
+        let (mut printer, written) = recording_printer();
+
+        // raster data before the print information
+        assert!(matches!(
+            printer.raster_line(&[0xFF; 90]),
+            Err(DriverError::InvalidSequence(JobPhase::Idle))
+        ));
+        assert!(written.borrow().is_empty());
+
+        let mut command = vec![0x67, 0x00, 90];
+        command.extend_from_slice(&[0xFF; 90]);
+
+        printer.send_command_unchecked(&command).unwrap();
+        assert_eq!(*written.borrow(), command);
+
+        // and the job phase stays where it was
+        assert!(matches!(
+            printer.print(),
+            Err(DriverError::InvalidSequence(JobPhase::Idle))
+        ));
+    }
+
+    #[test]
+    fn every_model_family_has_its_line_length() {
+        for (family, codes, bytes) in [
+            (
+                "QL-500/560/570/580N/650TD",
+                &[0x4F, 0x31, 0x32, 0x33, 0x51][..],
+                90,
+            ),
+            ("QL-700/710W/720NW", &[0x35, 0x36, 0x37], 90),
+            ("QL-800/810W/820NWB", &[0x38, 0x39, 0x41], 90),
+            ("QL-1050/1060N", &[0x50, 0x34], 162),
+            ("QL-1100/1110NWB/1115NWB", &[0x43, 0x44, 0x45], 162),
+            // nothing to go by, the common narrow head
+            ("unknown", &[0x00, 0xFF], 90),
+        ] {
+            for &code in codes {
+                let model = PrinterModel::from_code(code);
+                assert_eq!(model.line_bytes(), bytes, "{family} {code:#04x}");
+
+                // and the raster command says as much
+                let (mut printer, written) = recording_printer();
+                let status = parse_status(&reply(code, 62, 0, MediaType::Continuous)).unwrap();
+
+                printer
+                    .set_print_inforomation(&status, 1, PrintInfoFlags::default(), true)
+                    .unwrap();
+                written.borrow_mut().clear();
+
+                printer.raster_line(&vec![0xAA; bytes]).unwrap();
+
+                let written = written.borrow();
+                assert_eq!(written[..3], [0x67, 0x00, bytes as u8], "{family}");
+                assert_eq!(written.len(), 3 + bytes, "{family}");
+            }
+        }
+    }
+
+    #[test]
+    fn commands_go_to_the_given_device() {
+        // a plain file stands in for /dev/usb/lp1
+        let device = std::env::temp_dir().join(format!("printer_bot_rs_lp_{}", std::process::id()));
+        std::fs::write(&device, []).unwrap();
+
+        let mut printer = PrinterCommander::main(device.to_str().unwrap()).unwrap();
+        printer.reset().unwrap();
+        printer.initilize().unwrap();
+        printer.get_status().unwrap();
+        drop(printer);
+
+        let written = std::fs::read(&device).unwrap();
+        std::fs::remove_file(&device).unwrap();
+
+        let mut expected = vec![0x00; 200];
+        expected.extend([0x1b, 0x40, 0x1b, 0x69, 0x53]);
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn a_cancelled_job_drops_its_page() {
+        let (mut printer, written) = recording_printer();
+        let status = parse_status(&reply(QL_500, 62, 0, MediaType::Continuous)).unwrap();
+
+        let cancel = AtomicBool::new(true);
+        let options = PrintOptions {
+            cancel: Some(&cancel),
+            ..Default::default()
+        };
+
+        let lines = std::iter::repeat(vec![0xFF; 90]);
+
+        assert!(matches!(
+            printer.print_lines(&status, 10, lines, &options),
+            Err(DriverError::Cancelled)
+        ));
+        assert!(!cancel.load(Ordering::Relaxed));
+
+        // no line went out, and the printer was told to forget the page
+        let written = written.borrow();
+        assert!(!written.windows(3).any(|x| x == [0x67, 0x00, 90]));
+        assert!(written.ends_with(&[0x1b, 0x40]));
+    }
+
+    #[test]
+    fn every_media_has_its_range_and_length() {
+        for &(width, length, dots, offset, lines) in MEDIA {
+            let media_type = if length == 0 {
+                MediaType::Continuous
+            } else {
+                MediaType::DieCutLabels
+            };
+
+            let narrow = parse_status(&reply(QL_500, width, length, media_type)).unwrap();
+            let wide = parse_status(&reply(QL_1050, width, length, media_type)).unwrap();
+
+            assert_eq!(
+                narrow.printable_range(),
+                Some((offset, offset + dots - 1)),
+                "{width}x{length} on the QL-500"
+            );
+            assert_eq!(
+                wide.printable_range(),
+                Some((offset + 44, offset + 44 + dots - 1)),
+                "{width}x{length} on the QL-1050"
+            );
+
+            // the range fits on the head
+            let (_, last) = wide.printable_range().unwrap();
+            assert!((last as usize) < wide.model().line_bytes() * 8);
+
+            let max_lines = if length == 0 { 11811 } else { lines };
+            assert_eq!(narrow.max_lines(), Some(max_lines), "{width}x{length}");
+            assert_eq!(wide.max_lines(), Some(max_lines), "{width}x{length}");
+
+            assert!(Media { width, length }.is_known());
+            assert_eq!(Media { width, length }.printable_dots(), Some(dots));
+        }
+    }
+
+    #[test]
+    fn wide_labels_fill_the_wide_head() {
+        const QL_1100: u8 = 0x43;
+
+        let status = parse_status(&reply(QL_1100, 104, 164, MediaType::DieCutLabels)).unwrap();
+
+        assert_eq!(status.printable_range(), Some((56, 1255)));
+        assert_eq!(status.max_lines(), Some(1910));
+
+        // one black line as wide as the label
+        let (first_dot, last_dot) = status.printable_range().unwrap();
+        let width = (last_dot - first_dot + 1) as u32;
+
+        let raster = crate::raster::Raster {
+            pixels: vec![0; width as usize],
+            width,
+            rows: 0..1,
+            line_bytes: status.model().line_bytes(),
+            first_dot,
+            last_dot,
+            mirror: false,
+            high_resolution: false,
+            min_lines: 0,
+        };
+
+        let line = raster.lines().next().unwrap();
+        assert_eq!(line.len(), 162);
+
+        let dots = (0..line.len() * 8)
+            .filter(|dot| line[dot / 8] & (0x80 >> (dot % 8)) != 0)
+            .collect::<Vec<_>>();
+
+        assert_eq!(dots, (56..=1255).collect::<Vec<_>>());
+    }
+
+    // the print area tables of brother's raster command references, copied by hand and not
+    // from MEDIA: left margin, printable dots and right margin in pins of the head, then the
+    // label length in dots. the narrow models have a 720 pin head, the wide ones 1296
+    const REFERENCE: &[(u8, u8, u16, u16, u16, u32)] = &[
+        (12, 0, 585, 106, 29, 0),
+        (29, 0, 408, 306, 6, 0),
+        (38, 0, 295, 413, 12, 0),
+        (50, 0, 154, 554, 12, 0),
+        (54, 0, 130, 590, 0, 0),
+        (62, 0, 12, 696, 12, 0),
+        (17, 54, 555, 165, 0, 566),
+        (17, 87, 555, 165, 0, 956),
+        (23, 23, 476, 202, 42, 202),
+        (29, 42, 408, 306, 6, 425),
+        (29, 90, 408, 306, 6, 991),
+        (38, 90, 295, 413, 12, 991),
+        (39, 48, 289, 425, 6, 495),
+        (52, 29, 142, 578, 0, 271),
+        (62, 29, 12, 696, 12, 271),
+        (62, 100, 12, 696, 12, 1109),
+        (12, 12, 513, 94, 113, 94),
+        (24, 24, 442, 236, 42, 236),
+        (58, 58, 51, 618, 51, 618),
+    ];
+
+    // the same for the media that only fits the wide models
+    const WIDE_REFERENCE: &[(u8, u8, u16, u16, u16, u32)] = &[
+        (102, 0, 76, 1164, 56, 0),
+        (102, 51, 76, 1164, 56, 526),
+        (102, 152, 76, 1164, 56, 1660),
+        (104, 164, 40, 1200, 56, 1910),
+    ];
+
+    #[test]
+    fn media_matches_the_brother_reference() {
+        for (reference, model, pins) in [(REFERENCE, QL_500, 720), (WIDE_REFERENCE, QL_1050, 1296)]
+        {
+            for &(width, length, left, dots, right, lines) in reference {
+                // a typo in the copy would show up here first
+                assert_eq!(left + dots + right, pins, "{width}x{length}");
+
+                let media_type = if length == 0 {
+                    MediaType::Continuous
+                } else {
+                    MediaType::DieCutLabels
+                };
+                let status = parse_status(&reply(model, width, length, media_type)).unwrap();
+
+                // the raster line runs right to left, the first dot is after the right margin
+                assert_eq!(
+                    status.printable_range(),
+                    Some((right, right + dots - 1)),
+                    "{width}x{length}"
+                );
+                assert_eq!(
+                    Media { width, length }.printable_dots(),
+                    Some(dots),
+                    "{width}x{length}"
+                );
+
+                if length > 0 {
+                    assert_eq!(status.max_lines(), Some(lines), "{width}x{length}");
+                }
+            }
+        }
+
+        // and nothing in MEDIA that isn't in the references
+        for &(width, length, ..) in MEDIA {
+            assert!(
+                REFERENCE
+                    .iter()
+                    .chain(WIDE_REFERENCE)
+                    .any(|x| (x.0, x.1) == (width, length)),
+                "{width}x{length}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_media_has_no_range_or_length() {
+        for (width, length, media_type) in [
+            (63, 0, MediaType::Continuous),
+            (62, 30, MediaType::DieCutLabels),
+            // a size that only exists as die-cut labels
+            (17, 0, MediaType::Continuous),
+            (62, 0, MediaType::NoMedia),
+            (0, 0, MediaType::NoMedia),
+        ] {
+            let status = parse_status(&reply(QL_500, width, length, media_type)).unwrap();
+
+            assert_eq!(status.printable_range(), None, "{width}x{length}");
+            assert_eq!(status.max_lines(), None, "{width}x{length}");
+        }
+
+        assert!(!Media {
+            width: 62,
+            length: 30
+        }
+        .is_known());
+        assert_eq!(
+            Media {
+                width: 62,
+                length: 30
+            }
+            .printable_dots(),
+            None
+        );
+    }
+
+    // xorshift, the same bytes on every run
+    fn random_bytes(seed: &mut u64, length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 7;
+                *seed ^= *seed << 17;
+                *seed as u8
+            })
+            .collect()
+    }
+
+    // what the printer does with a packed line
+    fn unpack_bits(mut packed: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        while let [header, rest @ ..] = packed {
+            if *header < 128 {
+                let count = *header as usize + 1;
+                data.extend_from_slice(&rest[..count]);
+                packed = &rest[count..];
+            } else {
+                data.extend(std::iter::repeat_n(rest[0], 257 - *header as usize));
+                packed = &rest[1..];
+            }
+        }
+
+        data
+    }
+
+    #[test]
+    fn packed_lines_unpack_to_the_same_bytes() {
+        // literal bytes only
+        let literal = [1, 2, 3, 4, 5];
+        assert_eq!(pack_bits(&literal), [4, 1, 2, 3, 4, 5]);
+
+        // one byte repeated
+        let repeat = [7; 10];
+        assert_eq!(pack_bits(&repeat), [(257 - 10) as u8, 7]);
+
+        // runs longer than one header can hold, both kinds
+        let mut seed = 0x9E37_79B9_7F4A_7C15;
+        let long_literal = (0..300).map(|x| x as u8).collect::<Vec<_>>();
+        let long_repeat = [0xFF; 300];
+
+        for data in [
+            &literal[..],
+            &repeat,
+            &long_literal,
+            &long_repeat,
+            // a blank line of both models
+            &[0; 90],
+            &[0; 162],
+            // mixed, as a dithered picture packs
+            &[&[0; 40][..], &random_bytes(&mut seed, 10), &[0xFF; 40]].concat(),
+        ] {
+            assert_eq!(unpack_bits(&pack_bits(data)), data, "{data:?}");
+        }
+
+        // full lines of both models never grow past what the length byte can say
+        for length in [90, 162] {
+            for _ in 0..200 {
+                let line = random_bytes(&mut seed, length);
+                let packed = pack_bits(&line);
+
+                assert!(packed.len() <= length + 2);
+                assert_eq!(unpack_bits(&packed), line);
+            }
+        }
+    }
+
+    #[test]
+    fn short_or_garbled_replies_are_errors() {
+        let mut seed = 0x2545_F491_4F6C_DD1D;
+
+        for _ in 0..1000 {
+            for length in 0..32 {
+                let res = random_bytes(&mut seed, length);
+                assert!(parse_status(&res).is_err(), "{res:02x?}");
+            }
+
+            // a whole reply of noise may happen to parse, but never panics
+            let mut res = random_bytes(&mut seed, 32);
+            let _ = parse_status(&res);
+
+            // and a good reply cut short is still too short
+            res = reply(QL_500, 62, 0, MediaType::Continuous);
+            res.truncate(seed as usize % 32);
+            assert!(parse_status(&res).is_err());
+        }
+
+        // a media type, status type or phase no printer sends
+        for (index, value) in [(11, 0x0C), (18, 0x03), (19, 0x02)] {
+            let mut res = reply(QL_500, 62, 0, MediaType::Continuous);
+            res[index] = value;
+
+            assert!(parse_status(&res).is_err(), "byte {index} = {value:#04x}");
+        }
     }
 }
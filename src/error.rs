@@ -1,13 +1,84 @@
+use printer_bot_rs::driver::DriverError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum PrinterBotError {
     #[error("io error")]
     Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Driver(#[from] DriverError),
     #[error("teloxide error")]
     Teloxide(#[from] teloxide_core::RequestError),
     #[error("file download error")]
     Download(#[from] teloxide_core::DownloadError),
+    #[error("http error")]
+    Http(#[from] reqwest::Error),
+    #[error("not an image: {0:?}")]
+    NotAnImage(String),
+    #[error("download larger than {0} bytes")]
+    DownloadTooLarge(usize),
     #[error("image error")]
     Image(#[from] image::ImageError),
+    #[error("can't decode {format:?} image")]
+    DecodeFailed {
+        format: image::ImageFormat,
+        source: image::ImageError,
+    },
+    #[error("image ratio is too high: {0}")]
+    RatioTooHigh(f32),
+    #[error("image too large: {0}x{1}")]
+    ImageTooLarge(u32, u32),
+    #[error("invalid drawing command on line {0}")]
+    InvalidDrawing(usize),
+    #[error("unclosed placeholder in the template")]
+    InvalidTemplate,
+    #[error("no value for the template placeholder {0:?}")]
+    MissingTemplateValue(String),
+    #[error("the value for the template placeholder {0:?} is more than one line")]
+    InvalidTemplateValue(String),
+    #[error("no template named {0:?}")]
+    TemplateNotFound(String),
+    #[error("the table has no rows")]
+    EmptyTable,
+    #[error("font error")]
+    Font(#[from] ab_glyph::InvalidFont),
+    #[error("qr code error")]
+    Qr(#[from] qrcode::types::QrError),
+    #[error("print job didn't finish")]
+    Join(#[from] tokio::task::JoinError),
+    #[error("print queue closed")]
+    QueueClosed,
+    #[error("already running")]
+    AlreadyRunning,
+    #[error("{0:?} has no P-touch Template mode")]
+    TemplatesUnsupported(crate::driver::PrinterModel),
+    #[error("audio error")]
+    Audio(#[from] symphonia::core::errors::Error),
+    #[cfg(feature = "opus")]
+    #[error("opus error")]
+    Opus(#[from] audiopus::Error),
+    #[error("nothing printed yet")]
+    NothingToReprint,
+    #[error("tiff error")]
+    Tiff(#[from] tiff::TiffError),
+    #[error("unsupported tiff color type {0:?}")]
+    UnsupportedTiff(tiff::ColorType),
+    #[error("page {0} not found, the document has {1}")]
+    PageNotFound(usize, usize),
+    #[cfg(feature = "usb")]
+    #[error("usb error")]
+    Usb(#[from] rusb::Error),
+}
+
+impl PrinterBotError {
+    // worth resetting the printer and sending the job again, unlike media or cover problems
+    pub fn is_transient(&self) -> bool {
+        match self {
+            PrinterBotError::Io(_) => true,
+            PrinterBotError::Driver(err) => err.is_transient(),
+            #[cfg(feature = "usb")]
+            PrinterBotError::Usb(_) => true,
+            _ => false,
+        }
+    }
 }
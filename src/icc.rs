@@ -0,0 +1,50 @@
+use std::io::Read;
+
+use image::{ImageDecoder, ImageFormat};
+use log::*;
+
+// the image crate ignores embedded color profiles, so a wide gamut photo turns into gray
+// as if it was sRGB, usually looking washed out. not corrected, but at least visible in the log
+pub fn warn_if_not_srgb<R: Read>(reader: R, format: ImageFormat) {
+    let profile = match format {
+        ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(reader)
+            .ok()
+            .and_then(|mut x| x.icc_profile()),
+        ImageFormat::Png => image::codecs::png::PngDecoder::new(reader)
+            .ok()
+            .and_then(|mut x| x.icc_profile()),
+        ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(reader)
+            .ok()
+            .and_then(|mut x| x.icc_profile()),
+        _ => None,
+    };
+
+    let Some(profile) = profile else {
+        return;
+    };
+
+    if !is_srgb(&profile) {
+        warn!(
+            "image has a {} byte non-sRGB color profile ({}), grays may be off",
+            profile.len(),
+            color_space(&profile)
+        );
+    }
+}
+
+// v2 profiles describe themselves in ascii, v4 ones in utf-16
+fn is_srgb(profile: &[u8]) -> bool {
+    let ascii = b"sRGB";
+    let utf16 = [0, b's', 0, b'R', 0, b'G', 0, b'B'];
+
+    profile.windows(ascii.len()).any(|x| x == ascii)
+        || profile.windows(utf16.len()).any(|x| x == utf16)
+}
+
+// the color space from the profile header, e.g. "RGB " or "CMYK"
+fn color_space(profile: &[u8]) -> String {
+    profile
+        .get(16..20)
+        .map(|x| String::from_utf8_lossy(x).trim().to_string())
+        .unwrap_or_default()
+}
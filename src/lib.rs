@@ -0,0 +1,4 @@
+// the driver and the raster pipeline, for the bot and for printing from other programs
+pub mod dither;
+pub mod driver;
+pub mod raster;
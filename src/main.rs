@@ -1,24 +1,600 @@
+use std::borrow::Cow;
 use std::env;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use error::PrinterBotError;
 use log::*;
+use printer_bot_rs::driver::DriverError;
+use printer_bot_rs::raster::{self, Raster};
+use printer_bot_rs::{dither, driver};
 use teloxide_core::net::Download;
 use teloxide_core::types::ChatId;
 use teloxide_core::{
     payloads::GetUpdatesSetters,
     requests::{Requester, RequesterExt},
 };
+use tokio::sync::mpsc;
 
-mod driver;
+mod draw;
 mod error;
+mod icc;
+mod qr;
+mod text;
+mod tiff_page;
+#[cfg(feature = "usb")]
+mod usb;
+mod waveform;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    printer: PrinterConnection,
+    print_metadata: bool,
+    font_path: Option<String>,
+    max_queue_depth: usize,
+    trim_blank_lines: bool,
+    dither: dither::Dither,
+    gamma: f32,
+    cut_mode: driver::CutMode,
+    max_image_pixels: u64,
+    // where /template looks for `<name>.txt`, none disables it
+    template_dir: Option<PathBuf>,
+    dpi_600: bool,
+    crop_white_tolerance: Option<u8>,
+    mirror: bool,
+    embolden: bool,
+    registration_marks: bool,
+    probe_printer: bool,
+    print_retries: u32,
+    copies: u32,
+    zero_raster_graphics: bool,
+    compression: bool,
+    max_zero_raster_run: usize,
+    decode_qr: QrDecode,
+    orientation: Orientation,
+    fixed_width_mm: Option<f32>,
+    // none prints any shape
+    max_aspect_ratio: Option<f32>,
+    transparent_as_black: bool,
+    print_source_link: bool,
+    status_check_lines: Option<usize>,
+    fold_over: bool,
+    fold_over_gap_mm: f32,
+    cut_delay: Option<std::time::Duration>,
+    feed_margin: Option<u16>,
+    top_bar_dots: Option<u32>,
+    // set with /media when the tape reports the wrong size
+    media: Option<driver::Media>,
+    no_upscale: bool,
+    min_length_dots: Option<usize>,
+    footer: Footer,
+    sequence_file: PathBuf,
+    // every raster is also saved there as a png, for debugging
+    debug_output: Option<PathBuf>,
+    text_edges: text::Edges,
+    print_info_flags: driver::PrintInfoFlags,
+}
+
+// a small line under every print
+#[derive(Debug, Clone, Default)]
+struct Footer {
+    // 001, 002, ... counted in SEQUENCE_FILE so it survives restarts
+    sequence: bool,
+    timestamp: bool,
+}
+
+#[derive(Debug, Clone)]
+enum PrinterConnection {
+    Device(String),
+    // host:port of a network printer
+    Tcp(String),
+    // through libusb, optionally picking the printer by product id
+    #[cfg(feature = "usb")]
+    Usb(Option<u16>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Orientation {
+    // wide pictures are turned to run along continuous tape, die-cut labels are wider than
+    // long and take them as they are
+    Auto,
+    Portrait,
+    // turned a quarter clockwise
+    Landscape,
+    // turned a quarter counterclockwise
+    LandscapeCcw,
+    UpsideDown,
+}
+
+fn parse_orientation(value: &str) -> Option<Orientation> {
+    match value {
+        "portrait" => Some(Orientation::Portrait),
+        "landscape" => Some(Orientation::Landscape),
+        "landscape_ccw" => Some(Orientation::LandscapeCcw),
+        "upside_down" => Some(Orientation::UpsideDown),
+        "auto" => Some(Orientation::Auto),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum QrDecode {
+    Off,
+    // replies with the content of the qr code, then prints the picture as usual
+    Reply,
+    // replies with the content and prints a clean qr code instead of the picture
+    Reprint,
+}
+
+#[derive(Clone)]
+enum PrintJob {
+    File {
+        message: Box<teloxide_core::types::Message>,
+        file_id: String,
+        file_ext: String,
+    },
+    Contact {
+        chat_id: ChatId,
+        contact: teloxide_core::types::Contact,
+    },
+    Drawing {
+        chat_id: ChatId,
+        spec: String,
+    },
+    Label {
+        chat_id: ChatId,
+        text: String,
+    },
+    // a template from TEMPLATE_DIR with its placeholders filled in
+    Template {
+        chat_id: ChatId,
+        name: String,
+        values: Vec<(String, String)>,
+    },
+    Table {
+        chat_id: ChatId,
+        csv: String,
+    },
+    Poll {
+        chat_id: ChatId,
+        poll: teloxide_core::types::Poll,
+    },
+    // a csv sent as a file, downloaded by the worker
+    TableFile {
+        chat_id: ChatId,
+        file_id: String,
+    },
+    // these two go through the queue too, the worker owns the printer and the settings
+    Status {
+        chat_id: ChatId,
+    },
+    ShowSettings {
+        chat_id: ChatId,
+    },
+    Reprint {
+        chat_id: ChatId,
+    },
+    Preview {
+        chat_id: ChatId,
+    },
+    // None goes back to the media the printer reports
+    SetMedia {
+        chat_id: ChatId,
+        media: Option<driver::Media>,
+    },
+    Audio {
+        chat_id: ChatId,
+        file_id: String,
+        file_ext: String,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<(), PrinterBotError> {
     dotenvy::dotenv().ok();
     env_logger::init();
 
+    let settings = Settings {
+        // PRINTER_DEVICE is the name other brother tools use
+        printer: match env::var("PRINTER").or_else(|_| env::var("PRINTER_DEVICE")) {
+            Err(_) => PrinterConnection::Device("/dev/usb/lp0".to_string()),
+            #[cfg(feature = "usb")]
+            Ok(x) if x == "usb" => PrinterConnection::Usb(None),
+            #[cfg(feature = "usb")]
+            Ok(x) if x.starts_with("usb:") => PrinterConnection::Usb(Some(
+                u16::from_str_radix(&x["usb:".len()..], 16).expect("invalid PRINTER product id"),
+            )),
+            Ok(x) if x.starts_with("usb") => panic!("PRINTER={x} needs the usb feature"),
+            Ok(x) if x.starts_with("tcp://") => {
+                let address = &x["tcp://".len()..];
+
+                if address.contains(':') {
+                    PrinterConnection::Tcp(address.to_string())
+                } else {
+                    PrinterConnection::Tcp(format!("{address}:9100"))
+                }
+            }
+            Ok(x) => PrinterConnection::Device(x),
+        },
+        print_metadata: env::var("PRINT_METADATA").is_ok_and(|x| x == "true"),
+        font_path: env::var("FONT_PATH").ok(),
+        max_queue_depth: env::var("MAX_QUEUE_DEPTH")
+            .map(|x| x.parse().expect("invalid MAX_QUEUE_DEPTH"))
+            .unwrap_or(5),
+        trim_blank_lines: env::var("TRIM_BLANK_LINES").map_or(true, |x| x == "true"),
+        dither: env::var("DITHER").map_or(dither::Dither::FloydSteinberg, |x| {
+            dither::parse(&x).unwrap_or_else(|| panic!("invalid DITHER: {x}"))
+        }),
+        gamma: env::var("GAMMA")
+            .map(|x| {
+                x.parse()
+                    .ok()
+                    .filter(|&x: &f32| x > 0.0)
+                    .expect("invalid GAMMA")
+            })
+            .unwrap_or(raster::DEFAULT_GAMMA),
+        cut_mode: match env::var("CUT_MODE").as_deref() {
+            Err(_) | Ok("every_label") => driver::CutMode::EveryLabel,
+            Ok("at_end") => driver::CutMode::AtEnd,
+            Ok("never") => driver::CutMode::Never,
+            Ok(other) => panic!("invalid CUT_MODE: {other}"),
+        },
+        max_image_pixels: env::var("MAX_IMAGE_PIXELS")
+            .map(|x| x.parse().expect("invalid MAX_IMAGE_PIXELS"))
+            .unwrap_or(50_000_000),
+        template_dir: env::var("TEMPLATE_DIR").ok().map(PathBuf::from),
+        dpi_600: env::var("DPI_600").is_ok_and(|x| x == "true"),
+        crop_white_tolerance: env::var("CROP_WHITE_TOLERANCE")
+            .ok()
+            .map(|x| x.parse().expect("invalid CROP_WHITE_TOLERANCE")),
+        mirror: env::var("MIRROR").is_ok_and(|x| x == "true"),
+        embolden: env::var("EMBOLDEN").is_ok_and(|x| x == "true"),
+        registration_marks: env::var("REGISTRATION_MARKS").is_ok_and(|x| x == "true"),
+        probe_printer: env::var("PROBE_PRINTER").is_ok_and(|x| x == "true"),
+        copies: env::var("COPIES")
+            .map(|x| x.parse().ok().filter(|&x| x > 0).expect("invalid COPIES"))
+            .unwrap_or(1),
+        print_retries: env::var("PRINT_RETRIES")
+            .map(|x| x.parse().expect("invalid PRINT_RETRIES"))
+            .unwrap_or(2),
+        zero_raster_graphics: env::var("ZERO_RASTER_GRAPHICS").is_ok_and(|x| x == "true"),
+        compression: env::var("COMPRESSION").is_ok_and(|x| x == "true"),
+        max_zero_raster_run: env::var("MAX_ZERO_RASTER_RUN")
+            .map(|x| x.parse().expect("invalid MAX_ZERO_RASTER_RUN"))
+            .unwrap_or(usize::MAX),
+        decode_qr: match env::var("DECODE_QR").as_deref() {
+            Err(_) | Ok("off") => QrDecode::Off,
+            Ok("reply") => QrDecode::Reply,
+            Ok("reprint") => QrDecode::Reprint,
+            Ok(other) => panic!("invalid DECODE_QR: {other}"),
+        },
+        orientation: env::var("ORIENTATION")
+            .map(|x| parse_orientation(&x).expect("invalid ORIENTATION"))
+            .unwrap_or(Orientation::Portrait),
+        max_aspect_ratio: match env::var("MAX_ASPECT_RATIO").as_deref() {
+            Err(_) => Some(1.5),
+            Ok("off") => None,
+            Ok(x) => Some(
+                x.parse()
+                    .ok()
+                    .filter(|&x: &f32| x > 0.0)
+                    .expect("invalid MAX_ASPECT_RATIO"),
+            ),
+        },
+        fixed_width_mm: env::var("FIXED_WIDTH_MM")
+            .ok()
+            .map(|x| x.parse().expect("invalid FIXED_WIDTH_MM")),
+        transparent_as_black: env::var("TRANSPARENT_AS_BLACK").is_ok_and(|x| x == "true"),
+        print_source_link: env::var("PRINT_SOURCE_LINK").is_ok_and(|x| x == "true"),
+        status_check_lines: env::var("STATUS_CHECK_LINES")
+            .ok()
+            .map(|x| x.parse().expect("invalid STATUS_CHECK_LINES"))
+            .filter(|&x| x > 0),
+        fold_over: env::var("FOLD_OVER").is_ok_and(|x| x == "true"),
+        fold_over_gap_mm: env::var("FOLD_OVER_GAP_MM")
+            .map(|x| x.parse().expect("invalid FOLD_OVER_GAP_MM"))
+            .unwrap_or(5.0),
+        cut_delay: env::var("CUT_DELAY_MS")
+            .ok()
+            .map(|x| std::time::Duration::from_millis(x.parse().expect("invalid CUT_DELAY_MS"))),
+        feed_margin: env::var("FEED_MARGIN")
+            .ok()
+            .map(|x| x.parse().expect("invalid FEED_MARGIN")),
+        top_bar_dots: env::var("TOP_BAR_DOTS")
+            .ok()
+            .map(|x| x.parse().expect("invalid TOP_BAR_DOTS")),
+        media: None,
+        no_upscale: env::var("NO_UPSCALE").is_ok_and(|x| x == "true"),
+        min_length_dots: env::var("MIN_LENGTH_DOTS")
+            .ok()
+            .map(|x| x.parse().expect("invalid MIN_LENGTH_DOTS")),
+        footer: env::var("FOOTER").map_or(Footer::default(), |x| {
+            let mut footer = Footer::default();
+
+            for part in x.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+                match part {
+                    "sequence" => footer.sequence = true,
+                    "timestamp" => footer.timestamp = true,
+                    other => panic!("invalid FOOTER: {other}"),
+                }
+            }
+
+            footer
+        }),
+        debug_output: env::var("DEBUG_OUTPUT").ok().map(PathBuf::from),
+        sequence_file: env::var("SEQUENCE_FILE")
+            .unwrap_or_else(|_| "sequence.txt".to_string())
+            .into(),
+        text_edges: match env::var("TEXT_EDGES").as_deref() {
+            Err(_) | Ok("smooth") => text::Edges::Smooth,
+            Ok("hard") => text::Edges::Hard,
+            Ok(other) => panic!("invalid TEXT_EDGES: {other}"),
+        },
+        print_info_flags: env::var("PRINT_INFO_FLAGS").map_or(
+            driver::PrintInfoFlags::default(),
+            |x| {
+                let mut flags = driver::PrintInfoFlags {
+                    kind: false,
+                    width: false,
+                    length: false,
+                    quality: false,
+                    recover: false,
+                };
+
+                for part in x.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+                    match part {
+                        "kind" => flags.kind = true,
+                        "width" => flags.width = true,
+                        "length" => flags.length = true,
+                        "quality" => flags.quality = true,
+                        "recover" => flags.recover = true,
+                        other => panic!("invalid PRINT_INFO_FLAGS: {other}"),
+                    }
+                }
+
+                flags
+            },
+        ),
+    };
+
+    // `printer_bot_rs -` prints the image piped on stdin instead of starting the bot
+    // `--page N` picks the page of a multi-page tiff, `--orientation <orientation>` (or
+    // `--rotate`), `--copies N` and `--transparent-as-black` override ORIENTATION, COPIES and
+    // TRANSPARENT_AS_BLACK
+    if env::args().nth(1).as_deref() == Some("-") {
+        let mut settings = settings;
+        let mut page = 1;
+
+        let mut args = env::args().skip(2);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--page" => {
+                    page = args
+                        .next()
+                        .and_then(|x| x.parse().ok())
+                        .expect("invalid --page")
+                }
+                "--orientation" | "--rotate" => {
+                    settings.orientation = args
+                        .next()
+                        .and_then(|x| parse_orientation(&x))
+                        .unwrap_or_else(|| panic!("invalid {arg}"))
+                }
+                "--copies" => {
+                    settings.copies = args
+                        .next()
+                        .and_then(|x| x.parse().ok())
+                        .filter(|&x| x > 0)
+                        .expect("invalid --copies")
+                }
+                "--transparent-as-black" => settings.transparent_as_black = true,
+                other => panic!("unknown argument {other:?}"),
+            }
+        }
+
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+
+        if image::guess_format(&data).ok() == Some(image::ImageFormat::Tiff) {
+            let (img, pages) = tiff_page::decode_page(
+                std::io::Cursor::new(data),
+                page,
+                settings.max_image_pixels,
+            )?;
+            info!("printing page {} of {}", page, pages);
+
+            return print_picture(&img, &settings);
+        }
+
+        return print_bytes(&data, &settings);
+    }
+
+    // `printer_bot_rs status [--dump status.bin]` shows the printer status, optionally saving
+    // the raw reply so it can be attached to a bug report
+    if env::args().nth(1).as_deref() == Some("status") {
+        let mut printer = open_printer(&settings)?;
+
+        printer.reset()?;
+        printer.initilize()?;
+        printer.get_status()?;
+
+        let raw = printer.read_status_raw()?;
+
+        if env::args().nth(2).as_deref() == Some("--dump") {
+            let path = env::args().nth(3).expect("--dump needs a file name");
+            std::fs::write(&path, &raw)?;
+            info!("raw status written to {}", path);
+        }
+
+        println!("{:02x?}", raw);
+
+        return Ok(());
+    }
+
+    // `printer_bot_rs preview photo.jpg preview.png [width in dots]` renders like a print
+    // would, without a printer: one pixel per dot, at 696 dots for 62mm tape by default
+    if env::args().nth(1).as_deref() == Some("preview") {
+        let input = env::args().nth(2).expect("preview needs an image");
+        let output = env::args().nth(3).expect("preview needs an output file");
+        let width = env::args()
+            .nth(4)
+            .map(|x| x.parse::<u16>().expect("invalid preview width"))
+            .filter(|x| *x > 0)
+            .unwrap_or(PREVIEW_WIDTH);
+
+        let img = decode_image(open_image(Path::new(&input))?)?;
+
+        let preview = render_preview(&img, Placement::for_width(width, &settings), &settings)?;
+        std::fs::write(output, encode_png(preview)?)?;
+
+        return Ok(());
+    }
+
+    // `printer_bot_rs url https://example.com/image.png` downloads the image and prints it
+    if env::args().nth(1).as_deref() == Some("url") {
+        let url = env::args().nth(2).expect("url needs an address");
+
+        let data = download_image(&url).await?;
+
+        return print_bytes(&data, &settings);
+    }
+
+    // `printer_bot_rs batch <dir>` prints every image in the directory in name order
+    if env::args().nth(1).as_deref() == Some("batch") {
+        let dir = env::args().nth(2).expect("batch needs a directory");
+
+        let mut files = std::fs::read_dir(&dir)?
+            .map(|x| x.map(|x| x.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.sort();
+
+        let images = files
+            .into_iter()
+            .filter(|path| {
+                let supported = image::ImageFormat::from_path(path).is_ok_and(|x| x.can_read());
+
+                if !supported && path.is_file() {
+                    warn!("skipping {}, not a supported image", path.display());
+                }
+
+                supported
+            })
+            .collect::<Vec<_>>();
+
+        let mut failed = 0;
+
+        for (i, path) in images.iter().enumerate() {
+            println!("[{}/{}] {}", i + 1, images.len(), path.display());
+
+            if let Err(err) = print_file(path, &settings) {
+                error!("printing {} failed, {:?}", path.display(), err);
+                failed += 1;
+            }
+        }
+
+        println!("printed {}, failed {}", images.len() - failed, failed);
+
+        return Ok(());
+    }
+
+    // `printer_bot_rs template label.txt --set name=Widget --set price=3` fills the
+    // placeholders of a drawing template and prints it
+    if env::args().nth(1).as_deref() == Some("template") {
+        let path = PathBuf::from(env::args().nth(2).expect("template needs a file name"));
+
+        let mut values = Vec::new();
+        let mut args = env::args().skip(3);
+
+        while let Some(arg) = args.next() {
+            if arg != "--set" {
+                panic!("unknown template argument {arg:?}");
+            }
+
+            let value = args.next().expect("--set needs name=value");
+            let (key, value) = value.split_once('=').expect("--set needs name=value");
+
+            values.push((key.to_string(), value.to_string()));
+        }
+
+        let template = std::fs::read_to_string(&path)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+        let img = draw::render_template(&template, &values, &font, dir, settings.max_image_pixels)?;
+
+        return print_image(&image::DynamicImage::ImageLuma8(img), &settings);
+    }
+
+    // `printer_bot_rs stored-template 3` prints template 3 stored on the printer with
+    // P-touch Editor, on the models with P-touch Template mode
+    if env::args().nth(1).as_deref() == Some("stored-template") {
+        let number = env::args()
+            .nth(2)
+            .and_then(|x| x.parse::<u8>().ok())
+            .filter(|x| *x > 0)
+            .expect("invalid template number");
+
+        let (mut printer, status) = open_ready_printer(&settings)?;
+
+        if !status.model().supports_templates() {
+            return Err(PrinterBotError::TemplatesUnsupported(status.model()));
+        }
+
+        printer.set_command_mode(driver::CommandMode::PtouchTemplate)?;
+        printer.select_template(number)?;
+
+        return Ok(printer.print_template()?);
+    }
+
+    // `printer_bot_rs progress 75 Build` prints a bar 75% full, labeled "Build"
+    if env::args().nth(1).as_deref() == Some("progress") {
+        let percent = env::args()
+            .nth(2)
+            .and_then(|x| x.parse::<u8>().ok())
+            .filter(|x| *x <= 100)
+            .expect("invalid progress percentage");
+        let label = env::args().skip(3).collect::<Vec<_>>().join(" ");
+
+        let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+        let img = draw::render_progress_bar(&font, percent, &label, 720);
+
+        return print_image(&image::DynamicImage::ImageLuma8(img), &settings);
+    }
+
+    // `printer_bot_rs monitor` prints the status every time it changes, until ctrl-c,
+    // handy to see what the printer reports while opening the cover or changing the tape
+    if env::args().nth(1).as_deref() == Some("monitor") {
+        let mut previous = String::new();
+
+        loop {
+            let status = match read_printer_status(&settings) {
+                Ok(status) => format!("{status:?}"),
+                Err(err) => format!("not reachable: {err:?}"),
+            };
+
+            if status != previous {
+                println!("{status}");
+                previous = status;
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    }
+
+    // two bots would both poll telegram and fight over the printer, held until we exit
+    let lock = std::fs::File::create(env::temp_dir().join("printer_bot_rs.lock"))?;
+    if let Err(err) = lock.try_lock() {
+        return Err(match err {
+            std::fs::TryLockError::WouldBlock => PrinterBotError::AlreadyRunning,
+            std::fs::TryLockError::Error(err) => err.into(),
+        });
+    }
+
     let token = env::var("BOT_TOKEN").expect("BOT_TOKEN is not set");
-    let owner_id: ChatId = ChatId(
+    let mut owner_id: ChatId = ChatId(
         env::var("OWNER_ID")
             .expect("OWNER_ID is not set")
             .parse()
@@ -27,7 +603,34 @@ async fn main() -> Result<(), PrinterBotError> {
 
     let bot = teloxide_core::Bot::new(token).parse_mode(teloxide_core::types::ParseMode::Html);
 
-    bot.send_message(owner_id, "sto partendo").await?;
+    // with the printer off the first print would be the first sign of trouble, say it right away
+    if settings.probe_printer {
+        if let Err(err) = probe_printer(&settings) {
+            warn!("printer not reachable, {:?}", err);
+
+            if let Err(err) = bot
+                .send_message(owner_id, "printer not reachable, will retry")
+                .await
+            {
+                handle_chat_error(err.into(), &mut owner_id)?;
+            }
+
+            while let Err(err) = probe_printer(&settings) {
+                debug!("printer still not reachable, {:?}", err);
+                tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            }
+
+            info!("printer reachable");
+        }
+    }
+
+    if let Err(err) = bot.send_message(owner_id, "sto partendo").await {
+        handle_chat_error(err.into(), &mut owner_id)?;
+    }
+
+    let (queue, jobs) = mpsc::channel(settings.max_queue_depth);
+
+    tokio::spawn(print_worker(bot.clone(), jobs, settings));
 
     info!("Started polling");
 
@@ -46,14 +649,23 @@ async fn main() -> Result<(), PrinterBotError> {
                             continue;
                         }
 
-                        if let Some((file_id, file_ext)) =
-                            extract_photo_from_message(&bot, &message).await?
-                        {
-                            do_print(&bot, &file_id, &file_ext).await?;
+                        // the owner group became a supergroup, keep following it
+                        if let Some(new_id) = message.migrate_to_chat_id() {
+                            warn!("owner chat migrated to {}", new_id);
+                            owner_id = new_id;
+                            continue;
+                        }
+
+                        if let Err(err) = handle_message(&bot, &queue, message).await {
+                            handle_chat_error(err, &mut owner_id)?;
                         }
                     }
                 }
             }
+            Err(teloxide_core::RequestError::RetryAfter(duration)) => {
+                warn!("flood control, retrying after {:?}", duration);
+                tokio::time::sleep(duration).await;
+            }
             Err(err) => {
                 error!("{:?}", err);
                 bot.send_message(owner_id, format!("{:#?}", err)).await.ok();
@@ -62,6 +674,383 @@ async fn main() -> Result<(), PrinterBotError> {
     }
 }
 
+async fn handle_message(
+    bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    queue: &mpsc::Sender<PrintJob>,
+    message: teloxide_core::types::Message,
+) -> Result<(), PrinterBotError> {
+    if let Some((file_id, file_ext)) = extract_photo_from_message(bot, &message).await? {
+        let chat_id = message.chat.id;
+        let job = PrintJob::File {
+            message: Box::new(message),
+            file_id,
+            file_ext,
+        };
+        enqueue(bot, queue, chat_id, job).await?;
+    } else if let Some((file_id, file_ext)) = extract_audio_from_message(&message) {
+        let job = PrintJob::Audio {
+            chat_id: message.chat.id,
+            file_id,
+            file_ext,
+        };
+        enqueue(bot, queue, message.chat.id, job).await?;
+    } else if let Some(contact) = message.contact() {
+        let job = PrintJob::Contact {
+            chat_id: message.chat.id,
+            contact: contact.clone(),
+        };
+        enqueue(bot, queue, message.chat.id, job).await?;
+    } else if let Some(poll) = message.poll() {
+        let job = PrintJob::Poll {
+            chat_id: message.chat.id,
+            poll: poll.clone(),
+        };
+        enqueue(bot, queue, message.chat.id, job).await?;
+    } else if let Some(spec) = message.text().and_then(|x| x.strip_prefix("/draw")) {
+        let job = PrintJob::Drawing {
+            chat_id: message.chat.id,
+            spec: spec.to_string(),
+        };
+        enqueue(bot, queue, message.chat.id, job).await?;
+    } else if let Some(csv) = message.text().and_then(|x| x.strip_prefix("/table")) {
+        let job = PrintJob::Table {
+            chat_id: message.chat.id,
+            csv: csv.to_string(),
+        };
+        enqueue(bot, queue, message.chat.id, job).await?;
+    } else if let Some(args) = message.text().and_then(|x| x.strip_prefix("/template")) {
+        match parse_template_args(args) {
+            Some((name, values)) => {
+                let job = PrintJob::Template {
+                    chat_id: message.chat.id,
+                    name,
+                    values,
+                };
+                enqueue(bot, queue, message.chat.id, job).await?;
+            }
+            None => {
+                bot.send_message(
+                    message.chat.id,
+                    "usage: /template <i>name</i>, then one <i>placeholder</i>=<i>value</i> per line",
+                )
+                .await?;
+            }
+        }
+    } else if let Some(document) = message.document().filter(|x| is_csv(x)) {
+        let job = PrintJob::TableFile {
+            chat_id: message.chat.id,
+            file_id: document.file.id.clone(),
+        };
+        enqueue(bot, queue, message.chat.id, job).await?;
+    } else if let Some(args) = message.text().and_then(|x| x.strip_prefix("/media")) {
+        match parse_media(args) {
+            Some(media) => {
+                let job = PrintJob::SetMedia {
+                    chat_id: message.chat.id,
+                    media,
+                };
+                enqueue(bot, queue, message.chat.id, job).await?;
+            }
+            None => {
+                bot.send_message(
+                    message.chat.id,
+                    "usage: /media 62 continuous, /media 62x29 for die-cut labels, /media auto",
+                )
+                .await?;
+            }
+        }
+    } else if let Some(text) = message.text().and_then(|x| x.strip_prefix("/label")) {
+        let job = PrintJob::Label {
+            chat_id: message.chat.id,
+            text: text.to_string(),
+        };
+        enqueue(bot, queue, message.chat.id, job).await?;
+    } else if let Some(command) = message.text().and_then(command_name) {
+        let chat_id = message.chat.id;
+
+        match command {
+            "/status" => enqueue(bot, queue, chat_id, PrintJob::Status { chat_id }).await?,
+            "/settings" => enqueue(bot, queue, chat_id, PrintJob::ShowSettings { chat_id }).await?,
+            "/reprint" => enqueue(bot, queue, chat_id, PrintJob::Reprint { chat_id }).await?,
+            "/preview" => enqueue(bot, queue, chat_id, PrintJob::Preview { chat_id }).await?,
+            // not queued, it has to reach the job that is printing right now
+            "/cancel" => {
+                CANCEL.store(true, Ordering::Relaxed);
+                bot.send_message(chat_id, "cancelling the current print")
+                    .await?;
+            }
+            "/help" => {
+                bot.send_message(chat_id, HELP).await?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+const HELP: &str = "Send a photo or a sticker to print it.
+
+/label <i>name</i>, one more line per detail: a name badge or address label
+/draw: a drawing, one command per line (canvas, line, rect, text, qr)
+/table, one line per row with comma separated cells: a ruled table, csv files work too
+/template <i>name</i>, one <i>placeholder</i>=<i>value</i> per line: a label from a stored template
+/reprint: another copy of the last print
+/preview: the last print as sent to the printer, dot for dot
+/cancel: stop the print in progress
+/media 62 continuous, /media 62x29 or /media auto: the loaded tape, when the printer gets it wrong
+/status: the printer state
+/settings: the current settings
+
+Contacts are printed as a QR code, polls as a checklist, voice notes and audio files as a waveform. \
+Caption a photo with /thresholds to compare threshold levels, \
+with /sizes to see it at a few widths, or with /preview to see how it would print.";
+
+// `/status@printer_bot` -> `/status`
+fn command_name(text: &str) -> Option<&str> {
+    let word = text.split_whitespace().next()?;
+
+    word.starts_with('/')
+        .then(|| word.split('@').next().unwrap_or(word))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// errors about the owner chat itself, these shouldn't take the whole bot down
+fn handle_chat_error(err: PrinterBotError, owner_id: &mut ChatId) -> Result<(), PrinterBotError> {
+    use teloxide_core::{ApiError, RequestError};
+
+    match err {
+        PrinterBotError::Teloxide(RequestError::MigrateToChatId(new_id)) => {
+            warn!("owner chat migrated to {}", new_id);
+            *owner_id = ChatId(new_id);
+            Ok(())
+        }
+        PrinterBotError::Teloxide(RequestError::Api(ApiError::BotBlocked)) => {
+            warn!("the bot was blocked by the owner, skipping");
+            Ok(())
+        }
+        err => Err(err),
+    }
+}
+
+async fn enqueue(
+    bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    queue: &mpsc::Sender<PrintJob>,
+    chat_id: ChatId,
+    job: PrintJob,
+) -> Result<(), PrinterBotError> {
+    match queue.try_send(job) {
+        Ok(()) => Ok(()),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            warn!("print queue is full, rejecting job");
+            bot.send_message(chat_id, "queue full, try later").await?;
+            Ok(())
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => Err(PrinterBotError::QueueClosed),
+    }
+}
+
+impl PrintJob {
+    fn chat_id(&self) -> ChatId {
+        match self {
+            PrintJob::File { message, .. } => message.chat.id,
+            PrintJob::Contact { chat_id, .. }
+            | PrintJob::Drawing { chat_id, .. }
+            | PrintJob::Label { chat_id, .. }
+            | PrintJob::Template { chat_id, .. }
+            | PrintJob::Table { chat_id, .. }
+            | PrintJob::Poll { chat_id, .. }
+            | PrintJob::TableFile { chat_id, .. }
+            | PrintJob::Status { chat_id }
+            | PrintJob::ShowSettings { chat_id }
+            | PrintJob::Reprint { chat_id }
+            | PrintJob::Preview { chat_id }
+            | PrintJob::SetMedia { chat_id, .. }
+            | PrintJob::Audio { chat_id, .. } => *chat_id,
+        }
+    }
+}
+
+// another host printing is worth waiting for, the job is tried again after 10s, 20s, ...
+// about five minutes in all
+const BUSY_RETRIES: u32 = 5;
+
+// jobs are printed one at a time, in the order they were received
+async fn print_worker(
+    bot: teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    mut jobs: mpsc::Receiver<PrintJob>,
+    settings: Settings,
+) {
+    let mut settings = Arc::new(settings);
+
+    while let Some(job) = jobs.recv().await {
+        // a /cancel sent while nothing was printing doesn't stop the next job
+        CANCEL.store(false, Ordering::Relaxed);
+
+        let chat_id = job.chat_id();
+        let mut attempt = 0;
+
+        let result = loop {
+            match run_job(&bot, job.clone(), &mut settings).await {
+                // nothing was sent to the printer yet, the job stays first in the queue
+                Err(PrinterBotError::Driver(DriverError::PrinterInUse))
+                    if attempt < BUSY_RETRIES =>
+                {
+                    if attempt == 0 {
+                        bot.send_message(chat_id, "printer busy, queued").await.ok();
+                    }
+
+                    attempt += 1;
+
+                    let backoff = std::time::Duration::from_secs(5 << attempt);
+                    warn!(
+                        "printer is in use by another host, retrying in {:?} ({}/{})",
+                        backoff, attempt, BUSY_RETRIES
+                    );
+
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(PrinterBotError::Driver(DriverError::PrinterInUse)) => {
+                    warn!("printer still in use by another host, giving up");
+
+                    break bot
+                        .send_message(chat_id, "printer still busy, try again later")
+                        .await
+                        .map(|_| ())
+                        .map_err(Into::into);
+                }
+                result => break result,
+            }
+        };
+
+        if let Err(err) = result {
+            error!("print job failed, {:?}", err);
+        }
+    }
+}
+
+// a busy printer comes back as PrinterInUse, everything else is reported to the chat here
+async fn run_job(
+    bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    job: PrintJob,
+    settings: &mut Arc<Settings>,
+) -> Result<(), PrinterBotError> {
+    match job {
+        PrintJob::File {
+            message,
+            file_id,
+            file_ext,
+        } => do_print(bot, &message, &file_id, &file_ext, settings).await,
+        PrintJob::Contact { chat_id, contact } => {
+            let result =
+                blocking(settings, move |settings| print_contact(&contact, settings)).await;
+            report_print_result(bot, chat_id, result).await
+        }
+        PrintJob::Drawing { chat_id, spec } => {
+            let result = blocking(settings, move |settings| print_drawing(&spec, settings)).await;
+            report_print_result(bot, chat_id, result).await
+        }
+        PrintJob::Label { chat_id, text } => {
+            let result = blocking(settings, move |settings| print_label(&text, settings)).await;
+            report_print_result(bot, chat_id, result).await
+        }
+        PrintJob::Template {
+            chat_id,
+            name,
+            values,
+        } => {
+            let result = blocking(settings, move |settings| {
+                print_template(&name, &values, settings)
+            })
+            .await;
+            report_print_result(bot, chat_id, result).await
+        }
+        PrintJob::Poll { chat_id, poll } => {
+            let result = blocking(settings, move |settings| print_poll(&poll, settings)).await;
+            report_print_result(bot, chat_id, result).await
+        }
+        PrintJob::Table { chat_id, csv } => {
+            let result = blocking(settings, move |settings| print_table(&csv, settings)).await;
+            report_print_result(bot, chat_id, result).await
+        }
+        PrintJob::TableFile { chat_id, file_id } => {
+            do_print_table_file(bot, chat_id, &file_id, settings).await
+        }
+        PrintJob::Status { chat_id } => {
+            match blocking(settings, |settings| Ok(describe_printer_status(settings))).await {
+                Ok(reply) => bot
+                    .send_message(chat_id, reply)
+                    .await
+                    .map(|_| ())
+                    .map_err(Into::into),
+                Err(err) => Err(err),
+            }
+        }
+        PrintJob::Reprint { chat_id } => {
+            let result = blocking(settings, reprint).await;
+            report_print_result(bot, chat_id, result).await
+        }
+        // as a document, telegram would recompress a photo and blur the dots
+        PrintJob::Preview { chat_id } => match render_last_raster() {
+            Ok(png) => bot
+                .send_document(
+                    chat_id,
+                    teloxide_core::types::InputFile::memory(png).file_name("preview.png"),
+                )
+                .await
+                .map(|_| ())
+                .map_err(Into::into),
+            Err(err) => report_print_result(bot, chat_id, Err(err)).await,
+        },
+        PrintJob::SetMedia { chat_id, media } => {
+            Arc::make_mut(settings).media = media;
+
+            let reply = match media {
+                Some(media) if media.length == 0 => {
+                    format!("printing on {}mm continuous tape", media.width)
+                }
+                Some(media) => {
+                    format!("printing on {}x{}mm labels", media.width, media.length)
+                }
+                None => "using the media the printer reports".to_string(),
+            };
+
+            bot.send_message(chat_id, reply)
+                .await
+                .map(|_| ())
+                .map_err(Into::into)
+        }
+        PrintJob::ShowSettings { chat_id } => {
+            let reply = format!("<pre>{}</pre>", escape_html(&format!("{settings:#?}")));
+
+            bot.send_message(chat_id, reply)
+                .await
+                .map(|_| ())
+                .map_err(Into::into)
+        }
+        PrintJob::Audio {
+            chat_id,
+            file_id,
+            file_ext,
+        } => do_print_audio(bot, chat_id, &file_id, &file_ext, settings).await,
+    }
+}
+
+// printing blocks on the device and sleeps between retries, so it runs off the async workers
+async fn blocking<T: Send + 'static>(
+    settings: &Arc<Settings>,
+    job: impl FnOnce(&Settings) -> Result<T, PrinterBotError> + Send + 'static,
+) -> Result<T, PrinterBotError> {
+    let settings = Arc::clone(settings);
+
+    tokio::task::spawn_blocking(move || job(&settings)).await?
+}
+
 async fn extract_photo_from_message(
     bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
     message: &teloxide_core::types::Message,
@@ -74,6 +1063,24 @@ async fn extract_photo_from_message(
         }
     }
 
+    // sent as a file, so telegram didn't shrink it
+    if let Some(document) = message.document() {
+        if let Some(mime) = document
+            .mime_type
+            .as_ref()
+            .filter(|x| x.type_().as_str() == "image")
+        {
+            let file_ext = document
+                .file_name
+                .as_deref()
+                .and_then(|x| Path::new(x).extension())
+                .and_then(|x| x.to_str())
+                .unwrap_or(mime.subtype().as_str());
+
+            return Ok(Some((document.file.id.clone(), file_ext.to_string())));
+        }
+    }
+
     if let Some(sticker) = message.sticker() {
         if sticker.is_raster() {
             return Ok(Some((sticker.file.id.clone(), "webp".to_string())));
@@ -86,153 +1093,1847 @@ async fn extract_photo_from_message(
     Ok(None)
 }
 
+fn is_csv(document: &teloxide_core::types::Document) -> bool {
+    document
+        .mime_type
+        .as_ref()
+        .is_some_and(|x| x.essence_str() == "text/csv")
+        || document
+            .file_name
+            .as_deref()
+            .is_some_and(|x| x.to_ascii_lowercase().ends_with(".csv"))
+}
+
+// voice notes and audio files, printed as their waveform
+fn extract_audio_from_message(message: &teloxide_core::types::Message) -> Option<(String, String)> {
+    if let Some(voice) = message.voice() {
+        return Some((voice.file.id.clone(), "ogg".to_string()));
+    }
+
+    if let Some(audio) = message.audio() {
+        let file_ext = audio
+            .file_name
+            .as_deref()
+            .and_then(|x| Path::new(x).extension())
+            .and_then(|x| x.to_str())
+            .unwrap_or("mp3");
+
+        return Some((audio.file.id.clone(), file_ext.to_string()));
+    }
+
+    None
+}
+
 async fn do_print(
     bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    message: &teloxide_core::types::Message,
     file_id: &str,
     file_ext: &str,
+    settings: &Arc<Settings>,
 ) -> Result<(), PrinterBotError> {
     let file = bot.get_file(file_id).await?;
 
-    let file_path = format!("/tmp/toprint.{file_ext}");
+    let file_path = temp_file_path(file_ext);
 
     let mut dst = tokio::fs::File::create(&file_path).await?;
 
-    bot.download_file(&file.path, &mut dst).await?;
-
-    if let Err(err) = print_file(&file_path) {
-        error!("print failed, {:?}", err);
-    }
-
-    Ok(())
-}
+    let downloaded = bot.download_file(&file.path, &mut dst).await;
 
-fn print_file(file_path: &str) -> Result<(), PrinterBotError> {
-    debug!("printing file: {}", file_path);
+    let result = match downloaded {
+        Ok(()) => print_download(bot, message, &file_path, &file.path, settings).await,
+        Err(err) => Err(err.into()),
+    };
 
-    use image::io::Reader as ImageReader;
+    tokio::fs::remove_file(&file_path).await.ok();
 
-    let img = ImageReader::open(file_path)?.decode()?;
+    report_print_result(bot, message.chat.id, result).await
+}
 
-    // Limit stickers ratio (so people don't print incredibly long stickers)
+async fn print_download(
+    bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    message: &teloxide_core::types::Message,
+    file_path: &Path,
+    telegram_path: &str,
+    settings: &Arc<Settings>,
+) -> Result<(), PrinterBotError> {
+    let path = file_path.to_path_buf();
 
-    let ratio = img.width() as f32 / img.height() as f32;
+    if message.caption().is_some_and(|x| x.trim() == "/thresholds") {
+        return blocking(settings, move |settings| {
+            print_threshold_sheet(&path, settings)
+        })
+        .await;
+    }
 
-    if ratio > 1.5 {
-        println!("Ratio is too high: {}", ratio);
-        return Ok(());
+    if message.caption().is_some_and(|x| x.trim() == "/sizes") {
+        return blocking(settings, move |settings| print_size_sheet(&path, settings)).await;
     }
 
-    // remove transparency
-    let img = img.into_rgba8();
+    // sent back as it would print, without printing it
+    if message.caption().is_some_and(|x| x.trim() == "/preview") {
+        let png = blocking(settings, move |settings| {
+            let (width, height) = open_image(&path)?.into_dimensions()?;
+            check_image_size(width, height, settings)?;
 
-    let background_color = image::Rgba([255, 255, 255, 255]);
-    let mut background_image =
-        image::ImageBuffer::from_pixel(img.width(), img.height(), background_color);
-    image::imageops::overlay(&mut background_image, &img, 0, 0);
+            let img = decode_image(open_image(&path)?)?;
 
-    // convert to grayscale
+            encode_png(render_preview(&img, preview_placement(settings), settings)?)
+        })
+        .await?;
 
-    let img = image::imageops::grayscale(&background_image);
+        // as a document, telegram would recompress a photo and blur the dots
+        bot.send_document(
+            message.chat.id,
+            teloxide_core::types::InputFile::memory(png).file_name("preview.png"),
+        )
+        .await?;
 
-    // resize
+        return Ok(());
+    }
 
-    let new_width = 720;
+    // multi-page documents, `/page N` in the caption picks the page
+    if open_image(file_path)?.format() == Some(image::ImageFormat::Tiff) {
+        let page = message
+            .caption()
+            .and_then(|x| x.trim().strip_prefix("/page"))
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(1);
 
-    let new_height = new_width * img.height() / img.width();
+        let (img, pages) = blocking(settings, move |settings| {
+            let file = std::io::BufReader::new(std::fs::File::open(path)?);
+            tiff_page::decode_page(file, page, settings.max_image_pixels)
+        })
+        .await?;
 
-    let mut img = image::imageops::resize(
-        &img,
-        new_width,
-        new_height,
-        image::imageops::FilterType::Lanczos3,
-    );
+        bot.send_message(message.chat.id, format!("printing page {page} of {pages}"))
+            .await?;
 
-    // gamma correction
-    // match the brightness of the previous implementation
-    let gamma_correction = 5.14;
+        blocking(settings, move |settings| print_picture(&img, settings)).await?;
 
-    img.pixels_mut()
-        .for_each(|x| x.0 = [(255.0 * (x.0[0] as f32 / 255.0).powf(1.0 / gamma_correction)) as u8]);
+        return print_labels(message, file_path, telegram_path, settings).await;
+    }
 
-    use exoquant::*;
+    if settings.decode_qr != QrDecode::Off {
+        let content = blocking(settings, move |settings| read_qr(&path, settings)).await?;
 
-    let palette = vec![Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)];
+        if let Some(content) = content {
+            debug!("decoded qr code: {:?}", content);
 
-    let ditherer = ditherer::FloydSteinberg::vanilla();
-    let colorspace = SimpleColorSpace::default();
-    let remapper = Remapper::new(&palette, &colorspace, &ditherer);
+            let escaped = escape_html(&content);
+            bot.send_message(message.chat.id, format!("<code>{escaped}</code>"))
+                .await?;
 
-    let image = img
-        .pixels()
-        .map(|x| Color::new(x.0[0], x.0[0], x.0[0], 255))
-        .collect::<Vec<Color>>();
+            if settings.decode_qr == QrDecode::Reprint {
+                let img = qr::render_qr(&content, 1)?;
+                return blocking(settings, move |settings| {
+                    print_image_scaled(
+                        &image::DynamicImage::ImageLuma8(img),
+                        Scaling::Exact,
+                        settings,
+                    )
+                })
+                .await;
+            }
+        }
+    }
 
-    let indexed_data = remapper.remap(&image, img.width() as usize);
+    let path = file_path.to_path_buf();
+    blocking(settings, move |settings| print_file(&path, settings)).await?;
 
-    //debug_print_dithered(&indexed_data, img.width(), img.height())?;
+    // telegram shrinks photos, a small one is upscaled and comes out blurry. turned or not,
+    // and compared to the media, as the job that just printed it decided
+    if message.photo().is_some() {
+        let (width, height) = open_image(file_path)?.into_dimensions()?;
 
-    // convert to vec of line bits
+        let placement = last_status(settings).map(|x| Placement::for_status(&x, settings));
 
-    let mut lines = Vec::new();
+        let continuous = placement.as_ref().is_none_or(|x| x.continuous);
+        let media_width = placement.map_or(UPSCALE_WARNING_WIDTH, |x| {
+            (x.last_dot - x.first_dot + 1) as u32
+        });
 
-    for y in 0..img.height() {
-        let mut line = [0u8; 90];
+        let across = if rotates(settings.orientation, width, height, continuous) {
+            height
+        } else {
+            width
+        };
+
+        if across < media_width && !settings.no_upscale {
+            bot.send_message(
+                message.chat.id,
+                format!("this photo is only {across} pixels wide and came out blurry, send it as a file to print it at full resolution"),
+            )
+            .await?;
+        }
+    }
 
-        for x in 0..img.width() {
-            let i = y * img.width() + x;
-            let i = indexed_data[i as usize];
+    print_labels(message, file_path, telegram_path, settings).await
+}
 
-            let byte = x / 8;
-            let bit = x % 8;
+// the metadata and source link labels that follow a picture, when enabled
+async fn print_labels(
+    message: &teloxide_core::types::Message,
+    file_path: &Path,
+    telegram_path: &str,
+    settings: &Arc<Settings>,
+) -> Result<(), PrinterBotError> {
+    if settings.print_metadata {
+        let path = file_path.to_path_buf();
+        let telegram_path = telegram_path.to_string();
+        let message = message.clone();
+
+        blocking(settings, move |settings| {
+            print_metadata(&path, &telegram_path, &message, settings)
+        })
+        .await?;
+    }
 
-            if i == 0 {
-                line[89 - byte as usize] |= 1 << bit;
-            }
+    if settings.print_source_link {
+        if let Some(url) = source_link(message) {
+            blocking(settings, move |settings| print_source_link(&url, settings)).await?;
         }
-
-        lines.push(line);
     }
 
-    let mut printer = driver::PrinterCommander::main("/dev/usb/lp0")?;
-
-    printer.reset()?;
-    printer.initilize()?;
+    Ok(())
+}
 
-    // information
-    printer.get_status()?;
+async fn do_print_audio(
+    bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    chat_id: ChatId,
+    file_id: &str,
+    file_ext: &str,
+    settings: &Arc<Settings>,
+) -> Result<(), PrinterBotError> {
+    let file = bot.get_file(file_id).await?;
 
-    let status = printer.read_status()?;
-    trace!("{:#?}", status);
+    let file_path = temp_file_path(file_ext);
 
-    printer.set_raster_mode()?;
+    let mut dst = tokio::fs::File::create(&file_path).await?;
 
-    printer.set_print_inforomation(status, lines.len() as u32)?;
+    let downloaded = bot.download_file(&file.path, &mut dst).await;
 
-    //printer.set_margin_amount(35)?;
+    let result = match downloaded {
+        Ok(()) => {
+            let path = file_path.clone();
+            blocking(settings, move |settings| print_audio(&path, settings)).await
+        }
+        Err(err) => Err(err.into()),
+    };
 
-    debug!("printing {} lines", lines.len());
+    tokio::fs::remove_file(&file_path).await.ok();
 
-    for line in lines {
-        printer.raster_line(&line)?;
-    }
+    report_print_result(bot, chat_id, result).await
+}
 
-    printer.print_last_page()?;
+// small enough to keep in memory, a table label is a few lines anyway
+async fn do_print_table_file(
+    bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    chat_id: ChatId,
+    file_id: &str,
+    settings: &Arc<Settings>,
+) -> Result<(), PrinterBotError> {
+    let file = bot.get_file(file_id).await?;
+
+    let mut csv = Vec::new();
+
+    let result = match bot.download_file(&file.path, &mut csv).await {
+        Ok(()) => {
+            let csv = String::from_utf8_lossy(&csv).into_owned();
+            blocking(settings, move |settings| print_table(&csv, settings)).await
+        }
+        Err(err) => Err(err.into()),
+    };
+
+    report_print_result(bot, chat_id, result).await
+}
+
+// anything bigger is not a label
+const MAX_DOWNLOAD_BYTES: usize = 20_000_000;
+
+// only images, and never more than MAX_DOWNLOAD_BYTES whatever the server claims
+async fn download_image(url: &str) -> Result<Vec<u8>, PrinterBotError> {
+    let mut response = reqwest::get(url).await?.error_for_status()?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    if !content_type.starts_with("image/") {
+        return Err(PrinterBotError::NotAnImage(content_type));
+    }
 
-    trace!("{:#?}", printer.read_status()?);
-    trace!("{:#?}", printer.read_status()?);
-    trace!("{:#?}", printer.read_status()?);
+    let mut data = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        data.extend_from_slice(&chunk);
+
+        if data.len() > MAX_DOWNLOAD_BYTES {
+            return Err(PrinterBotError::DownloadTooLarge(MAX_DOWNLOAD_BYTES));
+        }
+    }
+
+    Ok(data)
+}
+
+// extensions we let into temp file names, they come from file names chosen by the sender
+const KNOWN_EXTENSIONS: [&str; 13] = [
+    "jpg", "jpeg", "png", "webp", "gif", "bmp", "tif", "tiff", "mp3", "ogg", "oga", "flac", "wav",
+];
+
+// unique for every job, so two jobs never read each other's downloads
+fn temp_file_path(file_ext: &str) -> PathBuf {
+    static JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let job = JOB_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    // anything else becomes .bin, the decoders look at the content anyway
+    let file_ext = file_ext.to_ascii_lowercase();
+    let file_ext = if KNOWN_EXTENSIONS.contains(&file_ext.as_str()) {
+        file_ext.as_str()
+    } else {
+        "bin"
+    };
+
+    env::temp_dir().join(format!("toprint-{}-{job}.{file_ext}", std::process::id()))
+}
+
+// by content rather than by extension, the name of the download can't be trusted
+fn open_image(
+    file_path: &Path,
+) -> Result<image::io::Reader<std::io::BufReader<std::fs::File>>, PrinterBotError> {
+    Ok(image::io::Reader::open(file_path)?.with_guessed_format()?)
+}
+
+// a corrupt file of a known format says which format it was supposed to be
+fn decode_image<R: std::io::BufRead + std::io::Seek>(
+    reader: image::io::Reader<R>,
+) -> Result<image::DynamicImage, PrinterBotError> {
+    let format = reader.format();
+
+    reader.decode().map_err(|err| match format {
+        Some(format) => PrinterBotError::DecodeFailed {
+            format,
+            source: err,
+        },
+        None => err.into(),
+    })
+}
+
+async fn report_print_result(
+    bot: &teloxide_core::adaptors::DefaultParseMode<teloxide_core::Bot>,
+    chat_id: ChatId,
+    result: Result<(), PrinterBotError>,
+) -> Result<(), PrinterBotError> {
+    match result {
+        Ok(()) => {}
+        // the worker waits for it and tries the job again
+        Err(err @ PrinterBotError::Driver(DriverError::PrinterInUse)) => return Err(err),
+        Err(PrinterBotError::Driver(DriverError::NotReady(problem))) => {
+            warn!("printer is not ready, {}", problem);
+            bot.send_message(chat_id, format!("printer not ready: {problem}"))
+                .await?;
+        }
+        Err(PrinterBotError::ImageTooLarge(width, height)) => {
+            warn!("refusing to decode a {}x{} image", width, height);
+            bot.send_message(chat_id, format!("image too large: {width}x{height}"))
+                .await?;
+        }
+        Err(PrinterBotError::Audio(symphonia::core::errors::Error::Unsupported(what))) => {
+            warn!("can't decode audio, unsupported {}", what);
+            bot.send_message(chat_id, "can't decode this audio format")
+                .await?;
+        }
+        Err(PrinterBotError::Driver(DriverError::PrintTooLong { lines, max })) => {
+            bot.send_message(
+                chat_id,
+                format!("too long for the loaded media: {lines} lines, at most {max}"),
+            )
+            .await?;
+        }
+        Err(PrinterBotError::Driver(DriverError::Cancelled)) => {
+            bot.send_message(chat_id, "print cancelled").await?;
+        }
+        Err(PrinterBotError::NothingToReprint) => {
+            bot.send_message(chat_id, "nothing to reprint yet").await?;
+        }
+        Err(PrinterBotError::PageNotFound(page, pages)) => {
+            bot.send_message(
+                chat_id,
+                format!("there is no page {page}, the document has {pages}"),
+            )
+            .await?;
+        }
+        Err(PrinterBotError::DecodeFailed { format, source }) => {
+            warn!("can't decode {:?} image, {:?}", format, source);
+            bot.send_message(
+                chat_id,
+                format!(
+                    "couldn't read that {}, is it corrupt?",
+                    format!("{format:?}").to_uppercase()
+                ),
+            )
+            .await?;
+        }
+        Err(PrinterBotError::EmptyTable) => {
+            bot.send_message(
+                chat_id,
+                "the table is empty, send one row per line with the cells separated by commas",
+            )
+            .await?;
+        }
+        Err(PrinterBotError::TemplateNotFound(name)) => {
+            bot.send_message(chat_id, format!("there is no template named {name}"))
+                .await?;
+        }
+        Err(PrinterBotError::MissingTemplateValue(name)) => {
+            bot.send_message(
+                chat_id,
+                format!("the template needs a value for {name}, add a line {name}=<i>value</i>"),
+            )
+            .await?;
+        }
+        Err(PrinterBotError::InvalidDrawing(line)) => {
+            bot.send_message(
+                chat_id,
+                format!("can't understand line {line} of the drawing"),
+            )
+            .await?;
+        }
+        Err(err) => {
+            error!("print failed, {:?}", err);
+        }
+    }
 
     Ok(())
 }
 
-#[allow(dead_code)]
-fn debug_print_dithered(data: &[u8], width: u32, height: u32) -> Result<(), PrinterBotError> {
-    let img = image::ImageBuffer::from_fn(width, height, |x, y| {
-        let i = y * width + x;
-        let i = data[i as usize];
-        image::Rgba([i * 255, i * 255, i * 255, 255])
-    });
-    img.save("/tmp/out_dithered.png")?;
+// prints the contact as a vCard QR code, so it can be scanned straight into a phone
+fn print_contact(
+    contact: &teloxide_core::types::Contact,
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    let vcard = match &contact.vcard {
+        Some(vcard) => vcard.clone(),
+        None => {
+            let first_name = &contact.first_name;
+            let last_name = contact.last_name.as_deref().unwrap_or("");
+            let full_name = format!("{first_name} {last_name}");
+
+            format!(
+                "BEGIN:VCARD\r\nVERSION:3.0\r\nN:{last_name};{first_name};;;\r\nFN:{}\r\nTEL:{}\r\nEND:VCARD\r\n",
+                full_name.trim(),
+                contact.phone_number
+            )
+        }
+    };
+
+    debug!("printing contact: {:?}", vcard);
+
+    let img = qr::render_qr(&vcard, 1)?;
+
+    print_image_scaled(
+        &image::DynamicImage::ImageLuma8(img),
+        Scaling::Exact,
+        settings,
+    )
+}
+
+// `name` is checked to be a plain file name before it gets here
+fn print_template(
+    name: &str,
+    values: &[(String, String)],
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    debug!("printing template {:?} with {:?}", name, values);
+
+    let Some(dir) = &settings.template_dir else {
+        warn!("template {:?} asked for, but TEMPLATE_DIR is not set", name);
+        return Err(PrinterBotError::TemplateNotFound(name.to_string()));
+    };
+
+    let template = match std::fs::read_to_string(dir.join(format!("{name}.txt"))) {
+        Ok(template) => template,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(PrinterBotError::TemplateNotFound(name.to_string()))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+    let img = draw::render_template(&template, values, &font, dir, settings.max_image_pixels)?;
+
+    print_image(&image::DynamicImage::ImageLuma8(img), settings)
+}
+
+fn print_drawing(spec: &str, settings: &Settings) -> Result<(), PrinterBotError> {
+    debug!("printing drawing: {:?}", spec);
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+    let img = draw::render_drawing(spec, &font)?;
+
+    print_image(&image::DynamicImage::ImageLuma8(img), settings)
+}
+
+// the question big, then every option with an empty box to tick, for the fridge door
+fn print_poll(
+    poll: &teloxide_core::types::Poll,
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    debug!("printing poll: {:?}", poll.question);
+
+    let options = poll
+        .options
+        .iter()
+        .map(|x| format!("[ ] {}", x.text))
+        .collect::<Vec<_>>();
+
+    let lines = std::iter::once(text::TextLine {
+        text: &poll.question,
+        size: 60.0,
+        align: text::Align::Left,
+    })
+    .chain(options.iter().map(|x| text::TextLine {
+        text: x,
+        size: 40.0,
+        align: text::Align::Left,
+    }))
+    .collect::<Vec<_>>();
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+    let img = text::render_text_block(&font, &lines, 720);
+
+    print_image(&image::DynamicImage::ImageLuma8(img), settings)
+}
+
+fn print_table(csv: &str, settings: &Settings) -> Result<(), PrinterBotError> {
+    debug!("printing table: {:?}", csv);
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+    let img = draw::render_table(csv, &font, 720)?;
+
+    print_image(&image::DynamicImage::ImageLuma8(img), settings)
+}
+
+fn print_audio(file_path: &Path, settings: &Settings) -> Result<(), PrinterBotError> {
+    debug!("printing audio: {}", file_path.display());
+
+    let img = waveform::render_waveform(file_path, 720)?;
+
+    print_image(&image::DynamicImage::ImageLuma8(img), settings)
+}
+
+fn read_qr(file_path: &Path, settings: &Settings) -> Result<Option<String>, PrinterBotError> {
+    let (width, height) = open_image(file_path)?.into_dimensions()?;
+    check_image_size(width, height, settings)?;
+
+    let img = decode_image(open_image(file_path)?)?;
+
+    Ok(qr::decode_qr(&img.to_luma8()))
+}
+
+// name badges and address labels: the first line big, the details under it small,
+// centered unless the line starts with `<` (left) or `>` (right)
+fn print_label(text: &str, settings: &Settings) -> Result<(), PrinterBotError> {
+    debug!("printing label: {:?}", text);
+
+    let lines = text
+        .trim()
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let (align, line) = if let Some(line) = line.strip_prefix('<') {
+                (text::Align::Left, line)
+            } else if let Some(line) = line.strip_prefix('>') {
+                (text::Align::Right, line)
+            } else {
+                (text::Align::Center, line)
+            };
+
+            text::TextLine {
+                text: line.trim(),
+                size: if i == 0 { 100.0 } else { 40.0 },
+                align,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+    let img = text::render_text_block(&font, &lines, 720);
+
+    print_image(&image::DynamicImage::ImageLuma8(img), settings)
+}
+
+fn print_file(file_path: &Path, settings: &Settings) -> Result<(), PrinterBotError> {
+    debug!("printing file: {}", file_path.display());
+
+    let (width, height) = open_image(file_path)?.into_dimensions()?;
+    check_image_size(width, height, settings)?;
+
+    if let Some(format) = open_image(file_path)?.format() {
+        icc::warn_if_not_srgb(
+            std::io::BufReader::new(std::fs::File::open(file_path)?),
+            format,
+        );
+    }
+
+    let img = decode_image(open_image(file_path)?)?;
+
+    print_picture(&img, settings)
+}
+
+fn print_bytes(data: &[u8], settings: &Settings) -> Result<(), PrinterBotError> {
+    debug!("printing {} bytes", data.len());
+
+    use image::io::Reader as ImageReader;
+
+    let (width, height) = ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    check_image_size(width, height, settings)?;
+
+    if let Ok(format) = image::guess_format(data) {
+        icc::warn_if_not_srgb(data, format);
+    }
+
+    let img = decode_image(ImageReader::new(std::io::Cursor::new(data)).with_guessed_format()?)?;
+
+    print_picture(&img, settings)
+}
+
+// only looks at the header, so a tiny file can't expand into gigabytes of pixels before we notice
+fn check_image_size(width: u32, height: u32, settings: &Settings) -> Result<(), PrinterBotError> {
+    if width as u64 * height as u64 > settings.max_image_pixels {
+        return Err(PrinterBotError::ImageTooLarge(width, height));
+    }
 
     Ok(())
 }
+
+// printable dots of 62mm tape, for the upscale warning before any job said what is loaded
+const UPSCALE_WARNING_WIDTH: u32 = 696;
+
+// whether the picture is turned a quarter, so its height runs across the tape. auto only
+// turns it on continuous tape, a die-cut label doesn't get any longer
+fn rotates(orientation: Orientation, width: u32, height: u32, continuous: bool) -> bool {
+    match orientation {
+        Orientation::Auto => continuous && width > height,
+        Orientation::Portrait | Orientation::UpsideDown => false,
+        Orientation::Landscape | Orientation::LandscapeCcw => true,
+    }
+}
+
+fn print_picture(img: &image::DynamicImage, settings: &Settings) -> Result<(), PrinterBotError> {
+    print_job(img, Scaling::Smooth, true, settings)
+}
+
+// the picture on its way to the raster, turned as ORIENTATION says for the loaded media and checked
+fn prepare_picture<'a>(
+    img: &'a image::DynamicImage,
+    continuous: bool,
+    settings: &Settings,
+) -> Result<Cow<'a, image::DynamicImage>, PrinterBotError> {
+    let turned = rotates(settings.orientation, img.width(), img.height(), continuous);
+
+    let img = match settings.orientation {
+        Orientation::LandscapeCcw => Cow::Owned(img.rotate270()),
+        Orientation::UpsideDown => Cow::Owned(img.rotate180()),
+        _ if turned => Cow::Owned(img.rotate90()),
+        _ => Cow::Borrowed(img),
+    };
+
+    // Limit stickers ratio (so people don't print incredibly long stickers)
+
+    let ratio = img.width() as f32 / img.height() as f32;
+
+    if settings.max_aspect_ratio.is_some_and(|max| ratio > max) {
+        return Err(PrinterBotError::RatioTooHigh(ratio));
+    }
+
+    Ok(img)
+}
+
+// what print_picture would print, one pixel per dot
+fn render_preview(
+    img: &image::DynamicImage,
+    placement: Placement,
+    settings: &Settings,
+) -> Result<image::GrayImage, PrinterBotError> {
+    let img = prepare_picture(img, placement.continuous, settings)?;
+
+    // no footer, it would take a number from the sequence
+    let raster = render_raster(&img, Scaling::Smooth, None, placement, settings)?;
+
+    Ok(raster_image(&raster))
+}
+
+// prints a small label with the name, size and date of the image that was just printed
+fn print_metadata(
+    file_path: &Path,
+    telegram_path: &str,
+    message: &teloxide_core::types::Message,
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    let (width, height) = open_image(file_path)?.into_dimensions()?;
+
+    let name = telegram_path.rsplit('/').next().unwrap_or(telegram_path);
+    let dimensions = format!("{width}x{height}");
+    let date = message.date.to_string();
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+    let label = text::render_text(&font, &[name, &dimensions, &date], 40.0, 720);
+
+    print_image(&image::DynamicImage::ImageLuma8(label), settings)
+}
+
+// the original message for forwards from channels and supergroups, otherwise the message itself
+// when it was sent in one. private chats and small groups have no t.me links
+fn source_link(message: &teloxide_core::types::Message) -> Option<String> {
+    let url = match (
+        message.forward_from_chat(),
+        message.forward_from_message_id(),
+    ) {
+        (Some(chat), Some(id)) => teloxide_core::types::Message::url_of(
+            chat.id,
+            chat.username(),
+            teloxide_core::types::MessageId(id),
+        ),
+        _ => message.url(),
+    };
+
+    url.map(|x| x.to_string())
+}
+
+// a small qr code with the link written next to it
+fn print_source_link(url: &str, settings: &Settings) -> Result<(), PrinterBotError> {
+    const QR_WIDTH: u32 = 240;
+
+    let qr = qr::render_qr(url, QR_WIDTH)?;
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+    let link = text::render_text(&font, &[url], 24.0, 720 - QR_WIDTH);
+
+    let mut img =
+        image::GrayImage::from_pixel(720, qr.height().max(link.height()), image::Luma([255]));
+    image::imageops::overlay(&mut img, &qr, 0, 0);
+    image::imageops::overlay(
+        &mut img,
+        &link,
+        QR_WIDTH as i64,
+        (qr.height() as i64 - link.height() as i64).max(0) / 2,
+    );
+
+    print_image(&image::DynamicImage::ImageLuma8(img), settings)
+}
+
+fn open_printer(settings: &Settings) -> Result<driver::PrinterCommander, PrinterBotError> {
+    match &settings.printer {
+        PrinterConnection::Device(path) => Ok(driver::PrinterCommander::main(path)?),
+        PrinterConnection::Tcp(address) => Ok(driver::PrinterCommander::new(Box::new(
+            driver::TcpPrinter::connect(address)?,
+        ))),
+        #[cfg(feature = "usb")]
+        PrinterConnection::Usb(product_id) => Ok(driver::PrinterCommander::new(Box::new(
+            usb::UsbPrinter::open(*product_id)?,
+        ))),
+    }
+}
+
+fn footer_text(settings: &Settings) -> Result<Option<String>, PrinterBotError> {
+    let mut parts = Vec::new();
+
+    if settings.footer.sequence {
+        parts.push(format!("{:03}", next_sequence(&settings.sequence_file)?));
+    }
+
+    if settings.footer.timestamp {
+        let now = chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now());
+        parts.push(now.format("%Y-%m-%d %H:%M UTC").to_string());
+    }
+
+    Ok((!parts.is_empty()).then(|| parts.join("  ")))
+}
+
+// the number stored in the file plus one, a missing file starts from 1
+fn next_sequence(path: &Path) -> Result<u64, PrinterBotError> {
+    let last = match std::fs::read_to_string(path) {
+        Ok(x) => x.trim().parse().unwrap_or(0),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(err) => return Err(err.into()),
+    };
+
+    std::fs::write(path, (last + 1).to_string())?;
+
+    Ok(last + 1)
+}
+
+// a full status round trip, so a printer that is there but doesn't answer fails too
+fn probe_printer(settings: &Settings) -> Result<(), PrinterBotError> {
+    trace!("{:#?}", read_printer_status(settings)?);
+
+    Ok(())
+}
+
+// the reply to /status
+fn describe_printer_status(settings: &Settings) -> String {
+    match read_printer_status(settings) {
+        Ok(status) => {
+            let ready = match status.is_ready() {
+                Ok(()) => "ready".to_string(),
+                Err(err) => err.to_string(),
+            };
+
+            let media = match status.media_type() {
+                driver::MediaType::NoMedia => "no media".to_string(),
+                driver::MediaType::Continuous => {
+                    format!("{}mm continuous tape", status.media_width_mm())
+                }
+                driver::MediaType::DieCutLabels => format!(
+                    "{}x{}mm labels",
+                    status.media_width_mm(),
+                    status.media_length_mm()
+                ),
+            };
+
+            let errors = status.errors();
+            let errors = if errors.is_empty() {
+                String::new()
+            } else {
+                format!("\nerrors: {}", errors.join(", "))
+            };
+
+            format!(
+                "{ready}, {media}{errors}\n<pre>{}</pre>",
+                escape_html(&format!("{status:#?}"))
+            )
+        }
+        Err(err) => format!(
+            "printer not reachable: {}",
+            escape_html(&format!("{err:?}"))
+        ),
+    }
+}
+
+fn read_printer_status(settings: &Settings) -> Result<driver::PrinterStatus, PrinterBotError> {
+    let mut printer = open_printer(settings)?;
+
+    printer.reset()?;
+    printer.initilize()?;
+    printer.get_status()?;
+
+    Ok(printer.read_status()?)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scaling {
+    // resampled to the printable width
+    Smooth,
+    // blown up by the largest whole factor that fits and centered, so every pixel becomes a
+    // block of dots, white margins included. for qr codes, resampling blurs the modules
+    Exact,
+    // one pixel per dot, centered, for pictures narrower than the tape with NO_UPSCALE
+    Native,
+}
+
+fn print_image(img: &image::DynamicImage, settings: &Settings) -> Result<(), PrinterBotError> {
+    print_image_scaled(img, Scaling::Smooth, settings)
+}
+
+fn print_image_scaled(
+    img: &image::DynamicImage,
+    scaling: Scaling,
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    print_job(img, scaling, false, settings)
+}
+
+// a usb hiccup shouldn't lose the job, reset the printer and send it all again. a `picture`
+// goes through prepare_picture once the printer told us what is loaded
+fn print_job(
+    img: &image::DynamicImage,
+    scaling: Scaling,
+    picture: bool,
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    // taken once, a retry prints the same number again
+    let footer = footer_text(settings)?;
+
+    let mut attempt = 0;
+
+    loop {
+        let open = || open_printer(settings);
+
+        match print_image_once(open, img, scaling, picture, footer.as_deref(), settings) {
+            Err(err) if err.is_transient() && attempt < settings.print_retries => {
+                attempt += 1;
+
+                let backoff = std::time::Duration::from_secs(1 << attempt);
+                warn!(
+                    "print failed, retrying in {:?} ({}/{}), {:?}",
+                    backoff, attempt, settings.print_retries, err
+                );
+
+                std::thread::sleep(backoff);
+            }
+            result => return result,
+        }
+    }
+}
+
+static LAST_RASTER: Mutex<Option<Raster>> = Mutex::new(None);
+
+// what the printer reported on the last job, to decide things later without opening it again
+static LAST_STATUS: Mutex<Option<driver::PrinterStatus>> = Mutex::new(None);
+
+// the last status with the media set now, none before the first job
+fn last_status(settings: &Settings) -> Option<driver::PrinterStatus> {
+    let status = LAST_STATUS.lock().unwrap().clone()?;
+
+    Some(match settings.media {
+        Some(media) => status.with_media(media),
+        None => status,
+    })
+}
+
+// where a preview goes without opening the printer: as on the last job, else on the media set
+// with /media, else on 62mm tape
+fn preview_placement(settings: &Settings) -> Placement {
+    if let Some(status) = last_status(settings) {
+        return Placement::for_status(&status, settings);
+    }
+
+    match settings.media {
+        Some(media) => Placement {
+            continuous: media.length == 0,
+            ..Placement::for_width(media.printable_dots().unwrap_or(PREVIEW_WIDTH), settings)
+        },
+        None => Placement::for_width(PREVIEW_WIDTH, settings),
+    }
+}
+
+// set by /cancel, checked between raster lines
+static CANCEL: AtomicBool = AtomicBool::new(false);
+
+fn open_ready_printer(
+    settings: &Settings,
+) -> Result<(driver::PrinterCommander, driver::PrinterStatus), PrinterBotError> {
+    ready_printer(open_printer(settings)?, settings)
+}
+
+// the handshake at the start of every job, the status it reads is used for the whole job
+fn ready_printer(
+    mut printer: driver::PrinterCommander,
+    settings: &Settings,
+) -> Result<(driver::PrinterCommander, driver::PrinterStatus), PrinterBotError> {
+    printer.reset()?;
+    printer.initilize()?;
+
+    // information
+    printer.get_status()?;
+
+    let status = printer.read_status()?;
+    trace!("{:#?}", status);
+
+    status.is_ready()?;
+
+    *LAST_STATUS.lock().unwrap() = Some(status.clone());
+
+    let status = match settings.media {
+        Some(media) => {
+            debug!("overriding the reported media with {:?}", media);
+            status.with_media(media)
+        }
+        None => status,
+    };
+
+    Ok((printer, status))
+}
+
+// the template name on the first line, letters, digits, `-` and `_` only so it can't leave
+// TEMPLATE_DIR, then `placeholder=value` lines
+fn parse_template_args(args: &str) -> Option<(String, Vec<(String, String)>)> {
+    let mut lines = args.lines();
+
+    let name = lines.next()?.trim();
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+
+    if name.is_empty() || !name.chars().all(valid) {
+        return None;
+    }
+
+    let values = lines
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| {
+            let (key, value) = x.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((name.to_string(), values))
+}
+
+// `62 continuous`, `62x29` or `62 29` for die-cut labels, `auto` to drop the override
+fn parse_media(args: &str) -> Option<Option<driver::Media>> {
+    let words = args
+        .split(|c: char| c.is_whitespace() || c == 'x')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+
+    let media = match words[..] {
+        ["auto"] => return Some(None),
+        [width, "continuous"] => driver::Media {
+            width: width.parse().ok()?,
+            length: 0,
+        },
+        [width, length] => driver::Media {
+            width: width.parse().ok()?,
+            length: length.parse().ok()?,
+        },
+        _ => return None,
+    };
+
+    media.is_known().then_some(Some(media))
+}
+
+// the printer is opened once per job, placing, turning and sending the picture all go by the
+// one status read when it was opened
+fn print_image_once(
+    open: impl Fn() -> Result<driver::PrinterCommander, PrinterBotError>,
+    img: &image::DynamicImage,
+    scaling: Scaling,
+    picture: bool,
+    footer: Option<&str>,
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    let (mut printer, status) = ready_printer(open()?, settings)?;
+
+    let placement = Placement::for_status(&status, settings);
+
+    let img = if picture {
+        prepare_picture(img, placement.continuous, settings)?
+    } else {
+        Cow::Borrowed(img)
+    };
+
+    let raster = render_raster(&img, scaling, footer, placement, settings)?;
+
+    send_raster(&mut printer, status, &raster, settings)?;
+
+    *LAST_RASTER.lock().unwrap() = Some(raster);
+
+    Ok(())
+}
+
+// where the picture goes on the raster lines, taken from the printer status, or given by
+// hand to render without a printer
+struct Placement {
+    line_bytes: usize,
+    first_dot: u16,
+    last_dot: u16,
+    high_resolution: bool,
+    continuous: bool,
+}
+
+// printable dots of 62mm tape, for previews without a printer
+const PREVIEW_WIDTH: u16 = 696;
+
+impl Placement {
+    fn for_status(status: &driver::PrinterStatus, settings: &Settings) -> Self {
+        let high_resolution = settings.dpi_600 && status.model().supports_high_resolution();
+
+        if settings.dpi_600 && !high_resolution {
+            warn!("{:?} can't print at 600 dpi, using 300 dpi", status.model());
+        }
+
+        // unknown media gets the whole line
+        let line_bytes = status.model().line_bytes();
+        let (first_dot, last_dot) = status
+            .printable_range()
+            .unwrap_or((0, line_bytes as u16 * 8 - 1));
+
+        Self {
+            line_bytes,
+            first_dot,
+            last_dot,
+            high_resolution,
+            continuous: status.media_type() == driver::MediaType::Continuous,
+        }
+    }
+
+    // the whole line of continuous tape, `width` dots wide
+    fn for_width(width: u16, settings: &Settings) -> Self {
+        Self {
+            line_bytes: (width as usize).div_ceil(8),
+            first_dot: 0,
+            last_dot: width - 1,
+            high_resolution: settings.dpi_600,
+            continuous: true,
+        }
+    }
+}
+
+fn render_raster(
+    img: &image::DynamicImage,
+    scaling: Scaling,
+    footer: Option<&str>,
+    placement: Placement,
+    settings: &Settings,
+) -> Result<Raster, PrinterBotError> {
+    let Placement {
+        line_bytes,
+        mut first_dot,
+        mut last_dot,
+        high_resolution,
+        ..
+    } = placement;
+
+    // remove transparency
+    let img = img.to_rgba8();
+
+    // black prints the transparent parts solid, for inverted designs
+    let background_color = if settings.transparent_as_black {
+        image::Rgba([0, 0, 0, 255])
+    } else {
+        image::Rgba([255, 255, 255, 255])
+    };
+    let mut background_image =
+        image::ImageBuffer::from_pixel(img.width(), img.height(), background_color);
+    image::imageops::overlay(&mut background_image, &img, 0, 0);
+
+    // convert to grayscale
+
+    let mut img = image::imageops::grayscale(&background_image);
+
+    // autocrop
+
+    // the white border of a qr code is its quiet zone, scanners need it on every side
+    let keep_margins = matches!(scaling, Scaling::Exact);
+
+    if let Some(tolerance) = settings.crop_white_tolerance.filter(|_| !keep_margins) {
+        img = autocrop(&img, tolerance);
+    }
+
+    // resize
+
+    // the same physical size whatever tape is loaded, centered on it
+    if let Some(width_mm) = settings.fixed_width_mm {
+        const DOTS_PER_MM: f32 = 300.0 / 25.4;
+
+        let available = last_dot - first_dot + 1;
+        let dots = ((width_mm * DOTS_PER_MM).round() as u16).max(1);
+
+        if dots > available {
+            warn!(
+                "{}mm doesn't fit on the loaded media, using all of it",
+                width_mm
+            );
+        } else {
+            first_dot += (available - dots) / 2;
+            last_dot = first_dot + dots - 1;
+        }
+    }
+
+    debug!("printing on dots {} to {}", first_dot, last_dot);
+
+    let new_width = (last_dot - first_dot + 1) as u32;
+
+    // at 600 dpi every line is half as tall, so twice as many are needed for the same label
+    let vertical = if high_resolution { 2 } else { 1 };
+
+    // small logos stay at their size instead of getting blurry
+    let scaling = match scaling {
+        Scaling::Smooth if settings.no_upscale && img.width() < new_width => Scaling::Native,
+        scaling => scaling,
+    };
+
+    let mut img = match scaling {
+        Scaling::Smooth => {
+            let mut img = raster::resize(&img, new_width, vertical);
+
+            raster::gamma_correct(&mut img, settings.gamma);
+
+            img
+        }
+        Scaling::Exact | Scaling::Native => {
+            let factor = match scaling {
+                Scaling::Exact => (new_width / img.width()).max(1),
+                _ => 1,
+            };
+
+            let scaled = image::imageops::resize(
+                &img,
+                img.width() * factor,
+                img.height() * factor * vertical,
+                image::imageops::FilterType::Nearest,
+            );
+
+            let mut img =
+                image::GrayImage::from_pixel(new_width, scaled.height(), image::Luma([255]));
+            image::imageops::overlay(
+                &mut img,
+                &scaled,
+                (new_width as i64 - scaled.width() as i64) / 2,
+                0,
+            );
+
+            if let Scaling::Native = scaling {
+                raster::gamma_correct(&mut img, settings.gamma);
+            }
+
+            img
+        }
+    };
+
+    if settings.fold_over {
+        const LINES_PER_MM: f32 = 300.0 / 25.4;
+
+        let mut gap = (settings.fold_over_gap_mm * LINES_PER_MM).round() as u32;
+        if high_resolution {
+            gap *= 2;
+        }
+
+        img = draw::add_fold_over_copy(&img, gap);
+    }
+
+    if let Some(dots) = settings.top_bar_dots {
+        img = draw::add_top_bar(&img, dots * vertical);
+    }
+
+    if let Some(footer) = footer {
+        let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+        let line = text::render_text(&font, &[footer], 30.0, img.width());
+        let line = image::imageops::resize(
+            &line,
+            line.width(),
+            line.height() * vertical,
+            image::imageops::FilterType::Nearest,
+        );
+
+        let mut with_footer = image::GrayImage::from_pixel(
+            img.width(),
+            img.height() + line.height(),
+            image::Luma([255]),
+        );
+        image::imageops::overlay(&mut with_footer, &img, 0, 0);
+        image::imageops::overlay(&mut with_footer, &line, 0, img.height() as i64);
+
+        img = with_footer;
+    }
+
+    if settings.registration_marks {
+        img = draw::add_registration_marks(&img, vertical);
+    }
+
+    let mut indexed_data = dither::dither(&img, settings.dither);
+
+    if settings.embolden {
+        indexed_data = dither::embolden(&indexed_data, img.width(), img.height());
+    }
+
+    let rows = if settings.trim_blank_lines && !keep_margins {
+        raster::trim_blank_rows(&indexed_data, img.width(), img.height())
+    } else {
+        0..img.height()
+    };
+
+    let raster = Raster {
+        pixels: indexed_data,
+        width: img.width(),
+        rows,
+        line_bytes,
+        first_dot,
+        last_dot,
+        mirror: settings.mirror,
+        high_resolution,
+        min_lines: settings.min_length_dots.unwrap_or(0) * vertical as usize,
+    };
+
+    if let Some(path) = &settings.debug_output {
+        std::fs::write(path, encode_png(raster_image(&raster))?)?;
+    }
+
+    Ok(raster)
+}
+
+// the last print as a png, one pixel per dot, exactly what was sent to the printer
+fn render_last_raster() -> Result<Vec<u8>, PrinterBotError> {
+    let last_raster = LAST_RASTER.lock().unwrap();
+    let raster = last_raster
+        .as_ref()
+        .ok_or(PrinterBotError::NothingToReprint)?;
+
+    encode_png(raster_image(raster))
+}
+
+// the lines that get printed, black and white
+fn raster_image(raster: &Raster) -> image::GrayImage {
+    image::GrayImage::from_fn(raster.width, raster.line_count() as u32, |x, y| {
+        if y >= raster.rows.len() as u32 {
+            return image::Luma([255]);
+        }
+
+        let y = raster.rows.start + y;
+        image::Luma([raster.pixels[(y * raster.width + x) as usize] * 255])
+    })
+}
+
+fn encode_png(img: image::GrayImage) -> Result<Vec<u8>, PrinterBotError> {
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageLuma8(img).write_to(&mut png, image::ImageOutputFormat::Png)?;
+
+    Ok(png.into_inner())
+}
+
+// sends the last raster again as it was, without downloading or rendering anything
+fn reprint(settings: &Settings) -> Result<(), PrinterBotError> {
+    let last_raster = LAST_RASTER.lock().unwrap();
+    let raster = last_raster
+        .as_ref()
+        .ok_or(PrinterBotError::NothingToReprint)?;
+
+    let (mut printer, status) = open_ready_printer(settings)?;
+
+    send_raster(&mut printer, status, raster, settings)
+}
+
+fn send_raster(
+    printer: &mut driver::PrinterCommander,
+    status: driver::PrinterStatus,
+    raster: &Raster,
+    settings: &Settings,
+) -> Result<(), PrinterBotError> {
+    let options = driver::PrintOptions {
+        copies: settings.copies,
+        cut_mode: settings.cut_mode,
+        high_resolution: raster.high_resolution,
+        feed_margin: settings.feed_margin,
+        compression: settings.compression,
+        print_info_flags: settings.print_info_flags,
+        cut_delay: settings.cut_delay,
+        status_check_lines: settings.status_check_lines,
+        zero_raster_graphics: settings.zero_raster_graphics,
+        max_zero_raster_run: settings.max_zero_raster_run,
+        cancel: Some(&CANCEL),
+    };
+
+    Ok(printer.print_lines(&status, raster.line_count(), raster.lines(), &options)?)
+}
+
+// crops to the content, pixels brighter than `255 - tolerance` count as background
+fn autocrop(img: &image::GrayImage, tolerance: u8) -> image::GrayImage {
+    let threshold = 255 - tolerance;
+
+    let (mut min_x, mut min_y) = (u32::MAX, u32::MAX);
+    let (mut max_x, mut max_y) = (0, 0);
+
+    for (x, y, pixel) in img.enumerate_pixels() {
+        if pixel.0[0] < threshold {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    if min_x > max_x {
+        // only background, nothing to crop to
+        return img.clone();
+    }
+
+    image::imageops::crop_imm(img, min_x, min_y, max_x - min_x + 1, max_y - min_y + 1).to_image()
+}
+
+// the same picture at a few widths one under the other, to pick a physical size for a logo
+fn print_size_sheet(file_path: &Path, settings: &Settings) -> Result<(), PrinterBotError> {
+    const PERCENTS: [u32; 3] = [100, 75, 50];
+    const SHEET_WIDTH: u32 = 720;
+
+    let (width, height) = open_image(file_path)?.into_dimensions()?;
+    check_image_size(width, height, settings)?;
+
+    let img = decode_image(open_image(file_path)?)?.to_luma8();
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+
+    let cells = PERCENTS
+        .into_iter()
+        .map(|percent| {
+            let width = (SHEET_WIDTH * percent / 100).max(1);
+
+            let mut tile = raster::resize(&img, width, 1);
+            raster::gamma_correct(&mut tile, settings.gamma);
+
+            let label = text::render_text_block(
+                &font,
+                &[text::TextLine {
+                    text: &format!("{percent}%"),
+                    size: 32.0,
+                    align: text::Align::Center,
+                }],
+                SHEET_WIDTH,
+            );
+
+            (tile, label)
+        })
+        .collect::<Vec<_>>();
+
+    let sheet_height = cells
+        .iter()
+        .map(|(tile, label)| tile.height() + label.height())
+        .sum();
+    let mut sheet = image::GrayImage::from_pixel(SHEET_WIDTH, sheet_height, image::Luma([255]));
+
+    let mut y = 0;
+
+    for (tile, label) in cells {
+        let x = (SHEET_WIDTH - tile.width()) / 2;
+
+        image::imageops::overlay(&mut sheet, &tile, x as i64, y as i64);
+        image::imageops::overlay(&mut sheet, &label, 0, (y + tile.height()) as i64);
+
+        y += tile.height() + label.height();
+    }
+
+    print_image(&image::DynamicImage::ImageLuma8(sheet), settings)
+}
+
+// the same picture at a few threshold levels side by side, to pick one for DITHER=threshold:<level>
+fn print_threshold_sheet(file_path: &Path, settings: &Settings) -> Result<(), PrinterBotError> {
+    const LEVELS: [u8; 4] = [96, 128, 160, 192];
+    const TILE_WIDTH: u32 = 360;
+    const LABEL_HEIGHT: u32 = 40;
+
+    let (width, height) = open_image(file_path)?.into_dimensions()?;
+    check_image_size(width, height, settings)?;
+
+    let img = decode_image(open_image(file_path)?)?.to_luma8();
+
+    let mut tile = raster::resize(&img, TILE_WIDTH, 1);
+    raster::gamma_correct(&mut tile, settings.gamma);
+
+    let font = text::load_font(settings.font_path.as_deref(), settings.text_edges)?;
+
+    let cell_height = tile.height() + LABEL_HEIGHT;
+    let mut sheet =
+        image::GrayImage::from_pixel(2 * TILE_WIDTH, 2 * cell_height, image::Luma([255]));
+
+    for (i, level) in LEVELS.into_iter().enumerate() {
+        let x = (i as u32 % 2) * TILE_WIDTH;
+        let y = (i as u32 / 2) * cell_height;
+
+        let indexed_data = dither::threshold(&tile, level);
+        let thresholded = image::GrayImage::from_fn(tile.width(), tile.height(), |x, y| {
+            image::Luma([indexed_data[(y * tile.width() + x) as usize] * 255])
+        });
+
+        let label = text::render_text_block(
+            &font,
+            &[text::TextLine {
+                text: &level.to_string(),
+                size: 32.0,
+                align: text::Align::Center,
+            }],
+            TILE_WIDTH,
+        );
+
+        image::imageops::overlay(&mut sheet, &thresholded, x as i64, y as i64);
+        image::imageops::overlay(&mut sheet, &label, x as i64, (y + tile.height()) as i64);
+    }
+
+    print_image(&image::DynamicImage::ImageLuma8(sheet), settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what main builds with no variables set
+    fn test_settings() -> Settings {
+        Settings {
+            printer: PrinterConnection::Device("/dev/usb/lp0".to_string()),
+            print_metadata: false,
+            font_path: None,
+            max_queue_depth: 5,
+            trim_blank_lines: true,
+            dither: dither::Dither::FloydSteinberg,
+            gamma: 5.14,
+            cut_mode: driver::CutMode::EveryLabel,
+            max_image_pixels: 50_000_000,
+            template_dir: None,
+            dpi_600: false,
+            crop_white_tolerance: None,
+            mirror: false,
+            embolden: false,
+            registration_marks: false,
+            probe_printer: false,
+            print_retries: 2,
+            copies: 1,
+            zero_raster_graphics: false,
+            compression: false,
+            max_zero_raster_run: usize::MAX,
+            decode_qr: QrDecode::Off,
+            orientation: Orientation::Portrait,
+            max_aspect_ratio: Some(1.5),
+            fixed_width_mm: None,
+            transparent_as_black: false,
+            print_source_link: false,
+            status_check_lines: None,
+            fold_over: false,
+            fold_over_gap_mm: 5.0,
+            cut_delay: None,
+            feed_margin: None,
+            top_bar_dots: None,
+            media: None,
+            no_upscale: false,
+            min_length_dots: None,
+            footer: Footer::default(),
+            sequence_file: "sequence.txt".into(),
+            debug_output: None,
+            text_edges: text::Edges::Smooth,
+            print_info_flags: driver::PrintInfoFlags::default(),
+        }
+    }
+
+    // a QL-500 with 62mm tape that is always ready, keeping every write as one command
+    struct Recorder {
+        writes: std::rc::Rc<std::cell::RefCell<Vec<Vec<u8>>>>,
+    }
+
+    impl driver::PrinterTransport for Recorder {
+        fn read(&mut self, _length: usize) -> Result<Vec<u8>, std::io::Error> {
+            let mut reply = vec![0; 32];
+            reply[..2].copy_from_slice(&[0x80, 0x20]);
+            reply[4] = 0x4F;
+            reply[10] = 62;
+            reply[11] = 0x0A;
+
+            Ok(reply)
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+            self.writes.borrow_mut().push(data.to_vec());
+            Ok(())
+        }
+
+        fn try_read(&mut self, _length: usize) -> Result<Option<Vec<u8>>, std::io::Error> {
+            Ok(None)
+        }
+    }
+
+    fn recording_printer() -> (
+        driver::PrinterCommander,
+        std::rc::Rc<std::cell::RefCell<Vec<Vec<u8>>>>,
+    ) {
+        let writes = std::rc::Rc::default();
+        let recorder = Recorder {
+            writes: std::rc::Rc::clone(&writes),
+        };
+
+        (driver::PrinterCommander::new(Box::new(recorder)), writes)
+    }
+
+    // black on the left, white on the right, so no row is trimmed as blank
+    fn gradient(width: u32, height: u32) -> image::DynamicImage {
+        image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(width, height, |x, _| {
+            image::Luma([(x * 255 / width) as u8])
+        }))
+    }
+
+    #[test]
+    fn preview_is_as_long_as_the_print() {
+        let img = gradient(100, 120);
+
+        for (dpi_600, min_length_dots) in [
+            (false, None),
+            (true, None),
+            (false, Some(400)),
+            (true, Some(400)),
+        ] {
+            let settings = Settings {
+                dpi_600,
+                min_length_dots,
+                ..test_settings()
+            };
+
+            let preview = render_preview(
+                &img,
+                Placement::for_width(PREVIEW_WIDTH, &settings),
+                &settings,
+            )
+            .unwrap();
+
+            let raster = render_raster(
+                &img,
+                Scaling::Smooth,
+                None,
+                Placement::for_width(PREVIEW_WIDTH, &settings),
+                &settings,
+            )
+            .unwrap();
+
+            assert_eq!(preview.height() as usize, raster.line_count());
+            assert_eq!(raster.lines().count(), raster.line_count());
+
+            // and one pixel per printable dot across
+            assert_eq!(preview.width(), PREVIEW_WIDTH as u32);
+            assert_eq!(preview.width(), raster.width);
+        }
+
+        // twice the lines for the same length of tape
+        let at = |dpi_600| {
+            let settings = Settings {
+                dpi_600,
+                ..test_settings()
+            };
+
+            render_preview(
+                &img,
+                Placement::for_width(PREVIEW_WIDTH, &settings),
+                &settings,
+            )
+            .unwrap()
+            .height()
+        };
+
+        assert_eq!(at(true), 2 * at(false));
+    }
+
+    #[test]
+    fn a_wide_picture_runs_along_continuous_tape() {
+        // solid, so no blank rows are trimmed off the length
+        let img = image::DynamicImage::ImageLuma8(image::GrayImage::new(2000, 500));
+
+        let lines = |orientation| {
+            let (printer, writes) = recording_printer();
+            let printer = std::cell::RefCell::new(Some(printer));
+
+            let settings = Settings {
+                orientation,
+                ..test_settings()
+            };
+
+            let open = || Ok(printer.borrow_mut().take().unwrap());
+            print_image_once(open, &img, Scaling::Smooth, true, None, &settings)?;
+
+            let writes = writes.borrow();
+            let info = writes
+                .iter()
+                .find(|x| x.starts_with(&[0x1B, 0x69, 0x7A]))
+                .unwrap();
+
+            Ok::<_, PrinterBotError>(u32::from_le_bytes(info[7..11].try_into().unwrap()))
+        };
+
+        // the 500 pixels across the 696 dots of the tape, the 2000 along it
+        assert_eq!(lines(Orientation::Auto).unwrap(), 2000 * 696 / 500);
+
+        // left as it is, it would be a short strip too wide for the aspect ratio limit
+        assert!(matches!(
+            lines(Orientation::Portrait),
+            Err(PrinterBotError::RatioTooHigh(_))
+        ));
+    }
+
+    #[test]
+    fn a_square_stays_square_at_600_dpi() {
+        let img = gradient(200, 200);
+
+        let at = |dpi_600| {
+            let settings = Settings {
+                dpi_600,
+                ..test_settings()
+            };
+
+            render_raster(
+                &img,
+                Scaling::Smooth,
+                None,
+                Placement::for_width(PREVIEW_WIDTH, &settings),
+                &settings,
+            )
+            .unwrap()
+        };
+
+        let (low, high) = (at(false), at(true));
+
+        // as wide across the tape, twice the lines of half the height along it
+        assert_eq!(low.width, PREVIEW_WIDTH as u32);
+        assert_eq!(high.width, low.width);
+        assert_eq!(low.rows.len(), low.width as usize);
+        assert_eq!(high.rows.len(), 2 * low.rows.len());
+    }
+
+    #[test]
+    fn every_copy_is_a_page_with_its_print_information() {
+        let (mut printer, writes) = recording_printer();
+
+        printer.get_status().unwrap();
+        let status = printer.read_status().unwrap();
+
+        let settings = Settings {
+            copies: 3,
+            ..test_settings()
+        };
+
+        let placement = Placement::for_status(&status, &settings);
+        let raster = render_raster(
+            &gradient(100, 120),
+            Scaling::Smooth,
+            None,
+            placement,
+            &settings,
+        )
+        .unwrap();
+
+        send_raster(&mut printer, status, &raster, &settings).unwrap();
+
+        let writes = writes.borrow();
+        let count = |command: &[u8]| writes.iter().filter(|x| x.as_slice() == command).count();
+
+        // print between the pages, print with feeding after the last one
+        assert_eq!(count(&[0x0C]), 2);
+        assert_eq!(count(&[0x1A]), 1);
+
+        let print_information = writes
+            .iter()
+            .filter(|x| x.starts_with(&[0x1B, 0x69, 0x7A]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(print_information.len(), 3);
+
+        for (page, command) in print_information.iter().enumerate() {
+            let lines = u32::from_le_bytes(command[7..11].try_into().unwrap());
+
+            assert_eq!(lines as usize, raster.line_count());
+            assert_eq!(command[11], if page == 0 { 0 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn a_job_opens_the_printer_once() {
+        let writes = std::rc::Rc::default();
+        let opens = std::cell::Cell::new(0);
+
+        let open = || {
+            opens.set(opens.get() + 1);
+
+            let recorder = Recorder {
+                writes: std::rc::Rc::clone(&writes),
+            };
+            Ok(driver::PrinterCommander::new(Box::new(recorder)))
+        };
+
+        let settings = Settings {
+            orientation: Orientation::Auto,
+            ..test_settings()
+        };
+
+        // wide, so auto orientation has to know the media to turn it
+        print_image_once(
+            open,
+            &gradient(300, 120),
+            Scaling::Smooth,
+            true,
+            None,
+            &settings,
+        )
+        .unwrap();
+
+        assert_eq!(opens.get(), 1);
+
+        let status_requests = writes
+            .borrow()
+            .iter()
+            .filter(|x| x.as_slice() == [0x1B, 0x69, 0x53])
+            .count();
+
+        assert_eq!(status_requests, 1);
+    }
+
+    #[test]
+    fn aspect_ratio_limit() {
+        let settings = test_settings();
+
+        // exactly 1.5 prints, a pixel more doesn't
+        assert!(prepare_picture(&gradient(150, 100), true, &settings).is_ok());
+        assert!(matches!(
+            prepare_picture(&gradient(151, 100), true, &settings),
+            Err(PrinterBotError::RatioTooHigh(_))
+        ));
+
+        let settings = Settings {
+            max_aspect_ratio: None,
+            ..test_settings()
+        };
+
+        assert!(prepare_picture(&gradient(1000, 10), true, &settings).is_ok());
+    }
+
+    #[test]
+    fn landscape_always_turns() {
+        let settings = Settings {
+            orientation: parse_orientation("landscape").unwrap(),
+            max_aspect_ratio: None,
+            ..test_settings()
+        };
+
+        for (width, height) in [(100, 300), (300, 100)] {
+            for continuous in [true, false] {
+                let img = gradient(width, height);
+                let img = prepare_picture(&img, continuous, &settings).unwrap();
+
+                assert_eq!((img.width(), img.height()), (height, width));
+            }
+        }
+    }
+
+    #[test]
+    fn debug_output_only_when_set() {
+        let path = env::temp_dir().join(format!("printer_bot_rs_debug_{}.png", std::process::id()));
+
+        let render = |debug_output| {
+            let settings = Settings {
+                debug_output,
+                ..test_settings()
+            };
+
+            render_raster(
+                &gradient(100, 120),
+                Scaling::Smooth,
+                None,
+                Placement::for_width(PREVIEW_WIDTH, &settings),
+                &settings,
+            )
+            .unwrap()
+        };
+
+        let raster = render(Some(path.clone()));
+        let written = image::open(&path).unwrap().into_luma8();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, raster_image(&raster));
+
+        render(None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn template_names_stay_in_the_template_dir() {
+        let (name, values) = parse_template_args(" price-tag\nname=Widget\n\nprice = 3\n").unwrap();
+
+        assert_eq!(name, "price-tag");
+        assert_eq!(
+            values,
+            [
+                ("name".to_string(), "Widget".to_string()),
+                ("price".to_string(), "3".to_string())
+            ]
+        );
+
+        for args in ["", " ../secret", " /etc/passwd", " a.b", " label\nno value"] {
+            assert!(parse_template_args(args).is_none(), "{args:?}");
+        }
+    }
+}
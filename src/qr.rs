@@ -0,0 +1,49 @@
+use image::{GrayImage, Luma};
+use qrcode::{Color, QrCode};
+
+use crate::error::PrinterBotError;
+
+// modules are drawn as square blocks, as big as they can be while fitting in `width`
+// together with the 4 module quiet zone the spec asks for. a `width` of 1 gives one pixel
+// per module
+pub fn render_qr(data: &str, width: u32) -> Result<GrayImage, PrinterBotError> {
+    const QUIET_ZONE: u32 = 4;
+
+    let code = QrCode::new(data.as_bytes())?;
+    let colors = code.to_colors();
+
+    let modules = code.width() as u32;
+    let module_size = (width / (modules + 2 * QUIET_ZONE)).max(1);
+    let size = (modules + 2 * QUIET_ZONE) * module_size;
+
+    let img = GrayImage::from_fn(size, size, |x, y| {
+        let x = (x / module_size) as i64 - QUIET_ZONE as i64;
+        let y = (y / module_size) as i64 - QUIET_ZONE as i64;
+
+        if x < 0 || y < 0 || x >= modules as i64 || y >= modules as i64 {
+            return Luma([255]);
+        }
+
+        match colors[(y * modules as i64 + x) as usize] {
+            Color::Dark => Luma([0]),
+            Color::Light => Luma([255]),
+        }
+    });
+
+    Ok(img)
+}
+
+// the first readable qr code in the image, if any
+pub fn decode_qr(img: &GrayImage) -> Option<String> {
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        img.width() as usize,
+        img.height() as usize,
+        |x, y| img.get_pixel(x as u32, y as u32).0[0],
+    );
+
+    prepared
+        .detect_grids()
+        .into_iter()
+        .find_map(|grid| grid.decode().ok())
+        .map(|(_, content)| content)
+}
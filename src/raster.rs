@@ -0,0 +1,138 @@
+use image::GrayImage;
+
+// the GAMMA the bot uses when none is set
+pub const DEFAULT_GAMMA: f32 = 5.14;
+
+// a dithered image placed on the raster lines, also kept after the job for /reprint
+pub struct Raster {
+    // one per pixel, 0 is black
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    // the rows that get printed
+    pub rows: std::ops::Range<u32>,
+    pub line_bytes: usize,
+    pub first_dot: u16,
+    pub last_dot: u16,
+    pub mirror: bool,
+    pub high_resolution: bool,
+    // a few lines of continuous tape don't feed far enough to be cut cleanly, blank lines
+    // after the rows make up the difference
+    pub min_lines: usize,
+}
+
+impl Raster {
+    // the lines sent to the printer, blank ones included
+    pub fn line_count(&self) -> usize {
+        self.rows.len().max(self.min_lines)
+    }
+
+    // packed one at a time, so a long banner never has all its packed lines in memory
+    pub fn lines(&self) -> impl Iterator<Item = Vec<u8>> + Clone + '_ {
+        let blank = std::iter::repeat(vec![0; self.line_bytes]);
+
+        self.rows
+            .clone()
+            .map(|y| self.line(y))
+            .chain(blank)
+            .take(self.line_count())
+    }
+
+    fn line(&self, y: u32) -> Vec<u8> {
+        let mut line = vec![0u8; self.line_bytes];
+
+        for x in 0..self.width {
+            let i = y * self.width + x;
+            let i = self.pixels[i as usize];
+
+            // the raster line runs right to left, unless mirrored for clear tape applied from the back
+            let dot = if self.mirror {
+                self.first_dot as u32 + x
+            } else {
+                self.last_dot as u32 - x
+            };
+
+            let byte = dot / 8;
+            let bit = 7 - dot % 8;
+
+            if i == 0 {
+                line[byte as usize] |= 1 << bit;
+            }
+        }
+
+        line
+    }
+}
+
+// resampled to `width` dots, `vertical` times taller for 600 dpi where lines are half as tall
+pub fn resize(img: &GrayImage, width: u32, vertical: u32) -> GrayImage {
+    let height = (width * img.height() / img.width() * vertical).max(1);
+
+    image::imageops::resize(img, width, height, image::imageops::FilterType::Lanczos3)
+}
+
+// brightens the midtones, thermal dots bleed and print darker than the picture. 1 leaves it
+// as is, the default 5.14 matches the brightness of the previous implementation
+pub fn gamma_correct(img: &mut GrayImage, gamma: f32) {
+    img.pixels_mut()
+        .for_each(|x| x.0 = [(255.0 * (x.0[0] as f32 / 255.0).powf(1.0 / gamma)) as u8]);
+}
+
+// the rows without the blank ones before and after the content, so the cut lands right at it
+pub fn trim_blank_rows(pixels: &[u8], width: u32, height: u32) -> std::ops::Range<u32> {
+    let is_blank = |y: u32| {
+        let row = (y * width) as usize..((y + 1) * width) as usize;
+        pixels[row].iter().all(|&x| x != 0)
+    };
+
+    let Some(start) = (0..height).find(|&y| !is_blank(y)) else {
+        // nothing to print at all, leave it to the printer
+        return 0..height;
+    };
+    let end = (0..height)
+        .rfind(|&y| !is_blank(y))
+        .map_or(height, |x| x + 1);
+
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamma_keeps_the_order_of_the_levels() {
+        let levels = GrayImage::from_fn(256, 1, |x, _| image::Luma([x as u8]));
+
+        for gamma in [0.5, 1.0, 2.2, DEFAULT_GAMMA] {
+            let mut img = levels.clone();
+            gamma_correct(&mut img, gamma);
+
+            assert!(
+                img.as_raw().windows(2).all(|x| x[0] <= x[1]),
+                "gamma {gamma}"
+            );
+            assert_eq!(img.as_raw()[0], 0, "gamma {gamma}");
+            assert_eq!(img.as_raw()[255], 255, "gamma {gamma}");
+        }
+
+        let mut img = levels.clone();
+        gamma_correct(&mut img, 1.0);
+
+        assert_eq!(img, levels);
+    }
+
+    #[test]
+    fn more_gamma_is_brighter() {
+        let mid_gray = GrayImage::from_pixel(1, 1, image::Luma([128]));
+
+        let levels = [0.5, 1.0, 1.5, 2.2, 3.0, DEFAULT_GAMMA, 8.0].map(|gamma| {
+            let mut img = mid_gray.clone();
+            gamma_correct(&mut img, gamma);
+            img.as_raw()[0]
+        });
+
+        assert!(levels.windows(2).all(|x| x[0] < x[1]), "{levels:?}");
+        // 1 leaves it alone
+        assert_eq!(levels[1], 128);
+    }
+}
@@ -0,0 +1,159 @@
+use ab_glyph::{point, Font as _, FontVec, PxScale, ScaleFont};
+use image::{GrayImage, Luma};
+
+use crate::error::PrinterBotError;
+
+// bundled so text printing works even where no fonts are installed
+const FALLBACK_FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+#[derive(Debug, Clone, Copy)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+// how glyph edges are turned into pixels
+#[derive(Debug, Clone, Copy)]
+pub enum Edges {
+    // anti-aliased, the gray edges go through the dither like the rest of the picture
+    Smooth,
+    // every pixel black or white already, crisper for small text
+    Hard,
+}
+
+pub struct Font {
+    glyphs: FontVec,
+    edges: Edges,
+}
+
+pub struct TextLine<'a> {
+    pub text: &'a str,
+    pub size: f32,
+    pub align: Align,
+}
+
+pub fn load_font(path: Option<&str>, edges: Edges) -> Result<Font, PrinterBotError> {
+    let data = match path {
+        Some(path) => std::fs::read(path)?,
+        None => FALLBACK_FONT.to_vec(),
+    };
+
+    Ok(Font {
+        glyphs: FontVec::try_from_vec(data)?,
+        edges,
+    })
+}
+
+// renders the lines left aligned, black on white, on an image `width` pixels wide
+pub fn render_text(font: &Font, lines: &[&str], size: f32, width: u32) -> GrayImage {
+    let lines = lines
+        .iter()
+        .map(|&text| TextLine {
+            text,
+            size,
+            align: Align::Left,
+        })
+        .collect::<Vec<_>>();
+
+    render_text_block(font, &lines, width)
+}
+
+// renders the lines top to bottom, each with its own size and alignment
+pub fn render_text_block(font: &Font, lines: &[TextLine], width: u32) -> GrayImage {
+    let line_height = |line: &TextLine| {
+        let font = font.glyphs.as_scaled(PxScale::from(line.size));
+        font.height() + font.line_gap()
+    };
+
+    let height = lines.iter().map(line_height).sum::<f32>().ceil() as u32;
+
+    let mut img = GrayImage::from_pixel(width, height.max(1), Luma([255]));
+
+    let mut top = 0.0;
+
+    for line in lines {
+        draw_line(&mut img, font, line, top);
+        top += line_height(line);
+    }
+
+    img
+}
+
+// how wide the text comes out on a single line
+pub fn text_width(font: &Font, text: &str, size: f32) -> f32 {
+    let font = font.glyphs.as_scaled(PxScale::from(size));
+
+    let mut width = 0.0;
+    let mut previous = None;
+
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        previous = Some(id);
+
+        width += font.h_advance(id);
+    }
+
+    width
+}
+
+fn draw_line(img: &mut GrayImage, font: &Font, line: &TextLine, top: f32) {
+    let edges = font.edges;
+    let font = font.glyphs.as_scaled(PxScale::from(line.size));
+
+    let baseline = top + font.ascent();
+
+    // lay the glyphs out first, the alignment needs the total width
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous = None;
+
+    for c in line.text.chars() {
+        let id = font.glyph_id(c);
+
+        if let Some(previous) = previous {
+            caret += font.kern(previous, id);
+        }
+        previous = Some(id);
+
+        glyphs.push(id.with_scale_and_position(font.scale(), point(caret, baseline)));
+        caret += font.h_advance(id);
+    }
+
+    let offset = match line.align {
+        Align::Left => 0.0,
+        Align::Center => ((img.width() as f32 - caret) / 2.0).max(0.0),
+        Align::Right => (img.width() as f32 - caret).max(0.0),
+    };
+
+    for mut glyph in glyphs {
+        glyph.position.x += offset;
+
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+
+        let bounds = outline.px_bounds();
+
+        outline.draw(|x, y, coverage| {
+            let x = bounds.min.x as i32 + x as i32;
+            let y = bounds.min.y as i32 + y as i32;
+
+            if x < 0 || y < 0 || x >= img.width() as i32 || y >= img.height() as i32 {
+                return;
+            }
+
+            let pixel = img.get_pixel_mut(x as u32, y as u32);
+            let value = match edges {
+                Edges::Smooth => (255.0 * (1.0 - coverage.clamp(0.0, 1.0))) as u8,
+                Edges::Hard if coverage >= 0.5 => 0,
+                Edges::Hard => 255,
+            };
+            pixel.0[0] = pixel.0[0].min(value);
+        });
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{Read, Seek};
+
+use image::{DynamicImage, ImageBuffer};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+use crate::error::PrinterBotError;
+
+// pages are counted from 1, returns the page and how many there are.
+// `max_pixels` is checked before the page is decoded, like for every other image
+pub fn decode_page<R: Read + Seek>(
+    reader: R,
+    page: usize,
+    max_pixels: u64,
+) -> Result<(DynamicImage, usize), PrinterBotError> {
+    let mut decoder = Decoder::new(reader)?;
+
+    let mut pages = 1;
+    while decoder.more_images() {
+        decoder.next_image()?;
+        pages += 1;
+    }
+
+    if page == 0 || page > pages {
+        return Err(PrinterBotError::PageNotFound(page, pages));
+    }
+
+    decoder.seek_to_image(page - 1)?;
+
+    let (width, height) = decoder.dimensions()?;
+    if width as u64 * height as u64 > max_pixels {
+        return Err(PrinterBotError::ImageTooLarge(width, height));
+    }
+
+    let color = decoder.colortype()?;
+
+    let img = match (color, decoder.read_image()?) {
+        // fax and scanner pages. white is zero for most of them, the decoder already turned
+        // those around so a set bit is always white here
+        (ColorType::Gray(1), DecodingResult::U8(data)) => {
+            let data = expand_bits(&data, width, height);
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        _ => None,
+    };
+
+    let img = img.ok_or(PrinterBotError::UnsupportedTiff(color))?;
+
+    Ok((img, pages))
+}
+
+// one bit per pixel, msb first, every row starts on a new byte
+fn expand_bits(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let row_bytes = (width as usize).div_ceil(8);
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for row in data.chunks(row_bytes).take(height as usize) {
+        for x in 0..width as usize {
+            let set = row.get(x / 8).is_some_and(|b| b & (0x80 >> (x % 8)) != 0);
+            pixels.push(if set { 255 } else { 0 });
+        }
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an uncompressed one strip bilevel tiff, 10 pixels wide so rows are padded to 2 bytes
+    fn bilevel(photometric: u16, rows: &[[u8; 2]]) -> std::io::Cursor<Vec<u8>> {
+        let entries: [(u16, u16, u32); 8] = [
+            (256, 3, 10),
+            (257, 3, rows.len() as u32),
+            (258, 3, 1),
+            (259, 3, 1),
+            (262, 3, photometric as u32),
+            (273, 4, 8 + 2 + 8 * 12 + 4),
+            (278, 3, rows.len() as u32),
+            (279, 4, rows.len() as u32 * 2),
+        ];
+
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend((entries.len() as u16).to_le_bytes());
+        for (tag, kind, value) in entries {
+            tiff.extend(tag.to_le_bytes());
+            tiff.extend(kind.to_le_bytes());
+            tiff.extend(1u32.to_le_bytes());
+            tiff.extend(value.to_le_bytes());
+        }
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(rows.iter().flatten());
+
+        std::io::Cursor::new(tiff)
+    }
+
+    #[test]
+    fn bilevel_pages_are_expanded() {
+        let rows = [[0b1000_0000, 0b0100_0000], [0b0111_1111, 0b1000_0000]];
+
+        let (img, pages) = decode_page(bilevel(1, &rows), 1, u64::MAX).unwrap();
+        assert_eq!(pages, 1);
+
+        let mut expected = vec![255, 0, 0, 0, 0, 0, 0, 0, 0, 255];
+        expected.extend([0, 255, 255, 255, 255, 255, 255, 255, 255, 0]);
+        assert_eq!(img.to_luma8().into_raw(), expected);
+    }
+
+    #[test]
+    fn white_is_zero_pages_are_not_negatives() {
+        let rows = [[0b1111_0000, 0b0000_0000]];
+
+        let (img, _) = decode_page(bilevel(0, &rows), 1, u64::MAX).unwrap();
+
+        assert_eq!(
+            img.to_luma8().into_raw(),
+            [0, 0, 0, 0, 255, 255, 255, 255, 255, 255]
+        );
+    }
+}
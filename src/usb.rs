@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use rusb::{Direction, TransferType, UsbContext};
+
+use crate::driver::PrinterTransport;
+use crate::error::PrinterBotError;
+
+const BROTHER_VENDOR_ID: u16 = 0x04f9;
+const PRINTER_CLASS: u8 = 0x07;
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+// talks to the printer through libusb, for systems without the usblp driver (macOS, Windows)
+pub struct UsbPrinter {
+    handle: rusb::DeviceHandle<rusb::GlobalContext>,
+    endpoint_in: u8,
+    endpoint_out: u8,
+}
+
+impl UsbPrinter {
+    // the first brother printer found, or the one with the given product id
+    pub fn open(product_id: Option<u16>) -> Result<Self, PrinterBotError> {
+        for device in rusb::GlobalContext::default().devices()?.iter() {
+            let descriptor = device.device_descriptor()?;
+
+            if descriptor.vendor_id() != BROTHER_VENDOR_ID
+                || product_id.is_some_and(|x| x != descriptor.product_id())
+            {
+                continue;
+            }
+
+            let config = device.active_config_descriptor()?;
+
+            for interface in config.interfaces() {
+                for setting in interface.descriptors() {
+                    if setting.class_code() != PRINTER_CLASS {
+                        continue;
+                    }
+
+                    let bulk = |direction| {
+                        setting
+                            .endpoint_descriptors()
+                            .find(|x| {
+                                x.transfer_type() == TransferType::Bulk
+                                    && x.direction() == direction
+                            })
+                            .map(|x| x.address())
+                    };
+
+                    let (Some(endpoint_in), Some(endpoint_out)) =
+                        (bulk(Direction::In), bulk(Direction::Out))
+                    else {
+                        continue;
+                    };
+
+                    let handle = device.open()?;
+
+                    // not supported everywhere, where it isn't there is no kernel driver to detach
+                    handle.set_auto_detach_kernel_driver(true).ok();
+                    handle.claim_interface(setting.interface_number())?;
+
+                    return Ok(Self {
+                        handle,
+                        endpoint_in,
+                        endpoint_out,
+                    });
+                }
+            }
+        }
+
+        Err(rusb::Error::NoDevice.into())
+    }
+}
+
+impl PrinterTransport for UsbPrinter {
+    fn read(&mut self, length: usize) -> Result<Vec<u8>, std::io::Error> {
+        let mut buf = vec![0u8; length];
+        let mut read = 0;
+
+        let mut tries = 0;
+
+        while read < length {
+            match self
+                .handle
+                .read_bulk(self.endpoint_in, &mut buf[read..], TIMEOUT)
+            {
+                Ok(n) => read += n,
+                Err(rusb::Error::Timeout) => {}
+                Err(err) => return Err(std::io::Error::other(err)),
+            }
+
+            tries += 1;
+
+            if tries > 10 && read < length {
+                return Err(std::io::Error::other("Timeout"));
+            }
+        }
+
+        Ok(buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        let mut written = 0;
+
+        while written < data.len() {
+            written += self
+                .handle
+                .write_bulk(self.endpoint_out, &data[written..], TIMEOUT * 50)
+                .map_err(std::io::Error::other)?;
+        }
+
+        Ok(())
+    }
+
+    fn try_read(&mut self, length: usize) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let mut buf = vec![0u8; length];
+
+        let read = match self
+            .handle
+            .read_bulk(self.endpoint_in, &mut buf, Duration::from_millis(1))
+        {
+            Ok(n) => n,
+            Err(rusb::Error::Timeout) => 0,
+            Err(err) => return Err(std::io::Error::other(err)),
+        };
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        if read < length {
+            buf[read..].copy_from_slice(&self.read(length - read)?);
+        }
+
+        Ok(Some(buf))
+    }
+}
@@ -0,0 +1,290 @@
+use std::path::Path;
+
+use image::{GrayImage, Luma};
+use log::*;
+use symphonia::core::audio::SampleBuffer;
+#[cfg(feature = "opus")]
+use symphonia::core::codecs::CODEC_TYPE_OPUS;
+use symphonia::core::codecs::{CodecParameters, DecoderOptions};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, Packet};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::error::PrinterBotError;
+
+// rows of label per second of audio
+const ROWS_PER_SECOND: u32 = 100;
+const MAX_ROWS: usize = 4000;
+
+// the time runs along the tape, the amplitude across it
+pub fn render_waveform(path: &Path, width: u32) -> Result<GrayImage, PrinterBotError> {
+    let envelope = decode_envelope(path)?;
+
+    // long recordings get squeezed instead of printing meters of tape
+    let step = envelope.len().div_ceil(MAX_ROWS).max(1);
+    let rows = envelope
+        .chunks(step)
+        .map(|x| x.iter().copied().fold(0.0, f32::max))
+        .collect::<Vec<_>>();
+
+    let loudest = rows.iter().copied().fold(0.0, f32::max).max(f32::EPSILON);
+
+    let mut img = GrayImage::from_pixel(width, rows.len().max(1) as u32, Luma([255]));
+    let center = width as f32 / 2.0;
+
+    for (y, peak) in rows.iter().enumerate() {
+        // at least one dot, so silence still shows up as a line
+        let half = (peak / loudest * center).max(1.0);
+
+        let from = (center - half).max(0.0) as u32;
+        let to = ((center + half) as u32).min(width);
+
+        for x in from..to {
+            img.put_pixel(x, y as u32, Luma([0]));
+        }
+    }
+
+    Ok(img)
+}
+
+// symphonia reads the ogg container of voice notes but has no opus decoder, libopus does that
+// with the opus feature
+enum AudioDecoder {
+    Symphonia(Box<dyn symphonia::core::codecs::Decoder>),
+    #[cfg(feature = "opus")]
+    Opus {
+        decoder: audiopus::coder::Decoder,
+        channels: usize,
+    },
+}
+
+impl AudioDecoder {
+    fn new(params: &CodecParameters) -> Result<Self, PrinterBotError> {
+        #[cfg(feature = "opus")]
+        if params.codec == CODEC_TYPE_OPUS {
+            return Self::opus(params);
+        }
+
+        // without the opus feature voice notes end up here, as an unsupported codec
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+
+        Ok(AudioDecoder::Symphonia(decoder))
+    }
+
+    #[cfg(feature = "opus")]
+    fn opus(params: &CodecParameters) -> Result<Self, PrinterBotError> {
+        let channels = match params.channels.map_or(1, |x| x.count()) {
+            1 => audiopus::Channels::Mono,
+            2 => audiopus::Channels::Stereo,
+            _ => return Err(Error::Unsupported("opus with more than 2 channels").into()),
+        };
+
+        Ok(AudioDecoder::Opus {
+            decoder: audiopus::coder::Decoder::new(audiopus::SampleRate::Hz48000, channels)?,
+            channels: channels as usize,
+        })
+    }
+
+    // opus always decodes at 48kHz, whatever the original was
+    fn sample_rate(&self, params: &CodecParameters) -> u32 {
+        match self {
+            AudioDecoder::Symphonia(_) => params.sample_rate.unwrap_or(48_000),
+            #[cfg(feature = "opus")]
+            AudioDecoder::Opus { .. } => 48_000,
+        }
+    }
+
+    // interleaved samples and the channel count, none for a corrupted packet
+    fn decode(&mut self, packet: &Packet) -> Result<Option<(Vec<f32>, usize)>, PrinterBotError> {
+        match self {
+            AudioDecoder::Symphonia(decoder) => {
+                let decoded = match decoder.decode(packet) {
+                    Ok(decoded) => decoded,
+                    Err(Error::DecodeError(err)) => {
+                        warn!("skipping audio packet, {}", err);
+                        return Ok(None);
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+
+                let spec = *decoded.spec();
+                let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                samples.copy_interleaved_ref(decoded);
+
+                Ok(Some((samples.samples().to_vec(), spec.channels.count())))
+            }
+            #[cfg(feature = "opus")]
+            AudioDecoder::Opus { decoder, channels } => {
+                // the longest opus packet, 120ms
+                let mut samples = vec![0.0; 5760 * *channels];
+
+                let frames = match decoder.decode_float(Some(packet.buf()), &mut samples, false) {
+                    Ok(frames) => frames,
+                    Err(audiopus::Error::Opus(err)) => {
+                        warn!("skipping audio packet, {:?}", err);
+                        return Ok(None);
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+
+                samples.truncate(frames * *channels);
+
+                Ok(Some((samples, *channels)))
+            }
+        }
+    }
+}
+
+// peak amplitude of every 1/ROWS_PER_SECOND of a second, all channels mixed
+fn decode_envelope(path: &Path) -> Result<Vec<f32>, PrinterBotError> {
+    let file = std::fs::File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|x| x.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+
+    let track = format
+        .default_track()
+        .ok_or(Error::Unsupported("no audio track"))?;
+    let track_id = track.id;
+
+    let mut decoder = AudioDecoder::new(&track.codec_params)?;
+    let sample_rate = decoder.sample_rate(&track.codec_params);
+
+    let frames_per_row = (sample_rate / ROWS_PER_SECOND).max(1) as usize;
+
+    let mut envelope = Vec::new();
+    let mut peak = 0.0f32;
+    let mut frames = 0;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        // a corrupted packet only loses a few milliseconds
+        let Some((samples, channels)) = decoder.decode(&packet)? else {
+            continue;
+        };
+
+        for frame in samples.chunks(channels.max(1)) {
+            peak = frame.iter().fold(peak, |peak, x| peak.max(x.abs()));
+            frames += 1;
+
+            if frames == frames_per_row {
+                envelope.push(peak);
+                peak = 0.0;
+                frames = 0;
+            }
+        }
+    }
+
+    if frames > 0 {
+        envelope.push(peak);
+    }
+
+    Ok(envelope)
+}
+
+#[cfg(all(test, feature = "opus"))]
+mod tests {
+    use super::*;
+
+    // a voice note as telegram sends it: mono opus in ogg, half a second of tone then silence
+    fn write_voice_note(path: &Path) {
+        const SERIAL: u32 = 1;
+        const FRAME: usize = 960;
+
+        let encoder = audiopus::coder::Encoder::new(
+            audiopus::SampleRate::Hz48000,
+            audiopus::Channels::Mono,
+            audiopus::Application::Voip,
+        )
+        .unwrap();
+
+        let mut writer = ogg::PacketWriter::new(std::fs::File::create(path).unwrap());
+
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, 1]);
+        head.extend_from_slice(&0u16.to_le_bytes());
+        head.extend_from_slice(&48_000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        writer
+            .write_packet(head.into(), SERIAL, ogg::PacketWriteEndInfo::EndPage, 0)
+            .unwrap();
+
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        writer
+            .write_packet(tags.into(), SERIAL, ogg::PacketWriteEndInfo::EndPage, 0)
+            .unwrap();
+
+        let frames = 50;
+
+        for i in 0..frames {
+            let samples = (0..FRAME)
+                .map(|n| {
+                    let t = (i * FRAME + n) as f32 / 48_000.0;
+                    let volume = if i < frames / 2 { 0.8 } else { 0.0 };
+                    volume * (t * 440.0 * std::f32::consts::TAU).sin()
+                })
+                .collect::<Vec<_>>();
+
+            let mut packet = vec![0; 4000];
+            let length = encoder.encode_float(&samples, &mut packet).unwrap();
+            packet.truncate(length);
+
+            let end = if i + 1 == frames {
+                ogg::PacketWriteEndInfo::EndStream
+            } else {
+                ogg::PacketWriteEndInfo::NormalPacket
+            };
+
+            writer
+                .write_packet(packet.into(), SERIAL, end, ((i + 1) * FRAME) as u64)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn voice_note_prints_its_waveform() {
+        let path = std::env::temp_dir().join("printer_bot_rs_voice_note_test.ogg");
+        write_voice_note(&path);
+
+        let img = render_waveform(&path, 100);
+        std::fs::remove_file(&path).ok();
+        let img = img.unwrap();
+
+        // a second of audio
+        assert!((95..=105).contains(&img.height()), "{}", img.height());
+
+        let black = |y: u32| {
+            (0..img.width())
+                .filter(|&x| img.get_pixel(x, y).0[0] == 0)
+                .count()
+        };
+
+        assert!(black(img.height() / 4) > 50);
+        assert!(black(img.height() * 7 / 8) < 10);
+    }
+}
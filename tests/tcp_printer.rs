@@ -0,0 +1,74 @@
+// a QL-820NWB on its raw port, played by a listener on localhost
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+use printer_bot_rs::driver::{PrintOptions, PrinterCommander, TcpPrinter};
+
+// 62mm continuous tape, nothing wrong
+fn status_reply() -> [u8; 32] {
+    let mut reply = [0; 32];
+    reply[..2].copy_from_slice(&[0x80, 0x20]);
+    reply[4] = 0x41;
+    reply[10] = 62;
+    reply[11] = 0x0A;
+
+    reply
+}
+
+#[test]
+fn a_job_reaches_a_network_printer() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let printer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // invalidate, initialize and the status request
+        let mut handshake = vec![0; 200 + 2 + 3];
+        stream.read_exact(&mut handshake).unwrap();
+
+        // the reply to it, then the three the job reads once it is printed
+        for _ in 0..4 {
+            stream.write_all(&status_reply()).unwrap();
+        }
+
+        let mut job = Vec::new();
+        stream.read_to_end(&mut job).unwrap();
+
+        (handshake, job)
+    });
+
+    let mut commander = PrinterCommander::new(Box::new(TcpPrinter::connect(&address).unwrap()));
+
+    commander.reset().unwrap();
+    commander.initilize().unwrap();
+    commander.get_status().unwrap();
+
+    let status = commander.read_status().unwrap();
+    assert_eq!(status.model().line_bytes(), 90);
+    assert_eq!(status.printable_range(), Some((12, 707)));
+
+    let lines = std::iter::repeat(vec![0xFF; 90]);
+    commander
+        .print_lines(&status, 3, lines, &PrintOptions::default())
+        .unwrap();
+    drop(commander);
+
+    let (handshake, job) = printer.join().unwrap();
+
+    let mut expected = vec![0x00; 200];
+    expected.extend([0x1b, 0x40, 0x1b, 0x69, 0x53]);
+    assert_eq!(handshake, expected);
+
+    // raster mode and the print information for 3 lines of 62mm tape
+    assert!(job.starts_with(&[0x1b, 0x69, 0x61, 0x01]));
+    assert!(job
+        .windows(11)
+        .any(|x| x[..3] == [0x1b, 0x69, 0x7a] && x[5] == 62 && x[7..11] == 3u32.to_le_bytes()));
+
+    let mut line = vec![0x67, 0x00, 90];
+    line.extend([0xFF; 90]);
+    assert_eq!(job.windows(line.len()).filter(|x| *x == line).count(), 3);
+
+    assert_eq!(job.last(), Some(&0x1A));
+}